@@ -1,17 +1,434 @@
-use std::env;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    env,
+    fmt::Write as _,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
 use anyhow::*;
 use fs_extra::{copy_items, dir::CopyOptions};
+use naga::{
+    back::glsl,
+    front::wgsl,
+    proc::BoundsCheckPolicies,
+    valid::{Capabilities, ValidationError, ValidationFlags, Validator},
+    Module, WithSpan,
+};
+
+// Decoding is only needed at runtime
+#[allow(dead_code)]
+#[path = "src/bake.rs"]
+mod bake;
+// Reading is only needed at runtime
+#[allow(dead_code)]
+#[path = "src/pack.rs"]
+mod pack;
+#[path = "src/preprocess.rs"]
+mod preprocess;
+// Writing is only needed at runtime
+#[allow(dead_code)]
+#[path = "src/tga.rs"]
+mod tga;
+
+use bake::{Kind, Manifest, ManifestEntry, MeshData, TextureData};
+
+/// A byte range of a shader that naga labelled in a diagnostic, and what it
+/// said about it.
+type Labelled = (Range<usize>, String);
 
 fn main() -> Result<()> {
     // This tells Cargo to rerun this script if something in /res/ changes.
-    println!("cargo:rerun-if-changed=res/*");
+    println!("cargo:rerun-if-changed=res");
 
     let out_dir = env::var("OUT_DIR")?;
     let mut copy_options = CopyOptions::new();
     copy_options.overwrite = true;
     let paths_to_copy = vec!["res/"];
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    copy_items(&paths_to_copy, &out_dir, &copy_options)?;
+
+    let baked_root = Path::new(&out_dir).join("res");
+    prune_copies("res", &baked_root)?;
+    let manifest = bake_assets("res", &baked_root)?;
+    let shipped = shipped_files(&baked_root, &manifest)?;
+    let pack_path = Path::new(&out_dir).join(pack::FILE_NAME);
+    write_pack(&shipped, &pack_path)?;
+
+    // The web build fetches assets from wherever the site serves them, so the
+    // Makefile asks for the baked ones to be copied there
+    println!("cargo:rerun-if-env-changed=ASSET_EXPORT_DIR");
+    if let Some(export_dir) = env::var_os("ASSET_EXPORT_DIR") {
+        let mut copy_options = CopyOptions::new();
+        copy_options.overwrite = true;
+        fs::create_dir_all(&export_dir)?;
+        copy_items(
+            &[baked_root.join(bake::DIR), pack_path],
+            export_dir,
+            &copy_options,
+        )?;
+    }
+
+    if env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_some() {
+        write_embedded_assets(&shipped, Path::new(&out_dir).join("embedded_assets.rs"))?;
+    }
+
+    let mut constants = String::new();
+    check_shaders("src", &mut constants)?;
+    let builtin = check_shaders("res", &mut constants)?;
+    write_builtin_files(&mut constants, &builtin)?;
+    fs::write(Path::new(&out_dir).join("shaders.rs"), constants)?;
+
+    Ok(())
+}
+
+/// Deletes the files a previous build copied from `root` to `out` whose
+/// sources have since been deleted. Baked assets are pruned by
+/// [`bake_assets`].
+fn prune_copies(root: &str, out: &Path) -> Result<()> {
+    for path in glob::glob(&format!("{}/**/*", out.display()))? {
+        let path = path?;
+        let relative = path.strip_prefix(out)?;
+        if path.is_file()
+            && !relative.starts_with(bake::DIR)
+            && !Path::new(root).join(relative).exists()
+        {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Bakes every texture and mesh under `root` into `out`, reusing what the
+/// last build baked for any source whose hash hasn't changed. Returns the
+/// manifest of what was baked.
+fn bake_assets(root: &str, out: &Path) -> Result<Manifest> {
+    let baked_dir = out.join(bake::DIR);
+    let manifest_path = baked_dir.join(bake::MANIFEST);
+    let previous = fs::read_to_string(&manifest_path)
+        .map(|text| Manifest::parse(&text))
+        .unwrap_or_default();
+    let mut manifest = Manifest::default();
+
+    for path in glob::glob(&format!("{}/**/*", root))? {
+        let path = path?;
+        let source = path
+            .strip_prefix(root)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let Some(kind) = Kind::of(&source) else {
+            continue;
+        };
+        let bytes = fs::read(&path)?;
+        let hash = bake::hash(&bytes);
+        let baked = bake::baked_path(&source, kind);
+        let baked_path = out.join(&baked);
+
+        let unchanged = previous
+            .entries
+            .get(&source)
+            .is_some_and(|entry| entry.hash == hash && entry.baked == baked);
+        if !unchanged || !baked_path.exists() {
+            let data = match kind {
+                Kind::Texture => tga::load_from_memory(&bytes, &source)
+                    .map(|image| TextureData::from_image(&image.to_rgba8()).encode())
+                    .with_context(|| format!("failed to decode {}", path.display()))?,
+                Kind::Mesh => bake_mesh(&path, &bytes)
+                    .with_context(|| format!("failed to read {}", path.display()))?
+                    .encode(),
+            };
+            fs::create_dir_all(baked_path.parent().unwrap())?;
+            fs::write(&baked_path, data)?;
+        }
+        manifest
+            .entries
+            .insert(source, ManifestEntry { hash, baked });
+    }
+
+    // Don't leave behind baked copies of sources that have been deleted
+    for (source, entry) in &previous.entries {
+        if !manifest.entries.contains_key(source) {
+            fs::remove_file(out.join(&entry.baked)).ok();
+        }
+    }
+
+    fs::create_dir_all(&baked_dir)?;
+    fs::write(manifest_path, manifest.to_string())?;
+    Ok(manifest)
+}
+
+fn bake_mesh(path: &Path, bytes: &[u8]) -> Result<MeshData> {
+    if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("obj"))
+    {
+        return Ok(MeshData::from_obj(std::str::from_utf8(bytes)?)?);
+    }
 
+    // Node transforms are ignored, so each mesh comes out in its own space
+    let (document, buffers, _) = gltf::import(path)?;
+    let mut data = MeshData::default();
+    for mesh in document.meshes() {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let base = data.vertices.len() as u32;
+            let mut tex_coords = reader.read_tex_coords(0).map(|t| t.into_f32());
+            for [x, y, z] in positions {
+                let [u, v] = tex_coords
+                    .as_mut()
+                    .and_then(Iterator::next)
+                    .unwrap_or([0.0, 0.0]);
+                data.vertices.push([x, y, z, u, v]);
+            }
+            match reader.read_indices() {
+                Some(indices) => data.indices.extend(indices.into_u32().map(|i| base + i)),
+                None => data.indices.extend(base..data.vertices.len() as u32),
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// The asset paths and locations of the files under `root` to ship in the
+/// pack and the binary: everything but the sources in `manifest`, which are
+/// only ever loaded in their baked form.
+fn shipped_files(root: &Path, manifest: &Manifest) -> Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    for path in glob::glob(&format!("{}/**/*", root.display()))? {
+        let path = path?;
+        if !path.is_file() {
+            continue;
+        }
+        let name = path
+            .strip_prefix(root)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if !manifest.entries.contains_key(&name) {
+            files.push((name, path));
+        }
+    }
+    Ok(files)
+}
+
+/// Packs `files` into a single file at `out`, so the web build can fetch one
+/// file instead of hundreds.
+fn write_pack(files: &[(String, PathBuf)], out: &Path) -> Result<()> {
+    let mut contents = Vec::new();
+    for (name, path) in files {
+        contents.push((name.as_str(), fs::read(path)?));
+    }
+    let packed = pack::write(contents.iter().map(|(name, data)| (*name, data.as_slice())));
+    fs::write(out, packed)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Writes a slice expression pairing the asset path of each of `files` with
+/// its contents, for the `embed-assets` feature to include.
+fn write_embedded_assets(files: &[(String, PathBuf)], out: impl AsRef<Path>) -> Result<()> {
+    let mut code = String::from("&[\n");
+    for (name, path) in files {
+        writeln!(code, "    ({:?}, include_bytes!({:?})),", name, path)?;
+    }
+    code.push(']');
+    fs::write(out, code)?;
+    Ok(())
+}
+
+/// Preprocesses and validates every shader under `root` for both native and
+/// WebGL2 targets, and appends constants describing each one to `constants`.
+/// Files that other files include are only checked as part of those files,
+/// since they may not stand on their own. Returns the name and path of every
+/// shader found.
+fn check_shaders(root: &str, constants: &mut String) -> Result<Vec<(String, PathBuf)>> {
+    // The directory too, so shaders that are added are checked. Cargo scans
+    // directories recursively, so this reruns the script for any change
+    // under `root`
+    println!("cargo:rerun-if-changed={}", root);
+    let mut files = BTreeMap::new();
+    let mut paths = Vec::new();
+    for path in glob::glob(&format!("{}/**/*.wgsl", root))? {
+        let path = path?;
+        println!("cargo:rerun-if-changed={}", path.display());
+        // Key files the same way includes are resolved at runtime
+        let name = path
+            .strip_prefix(root)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        files.insert(name.clone(), fs::read_to_string(&path)?);
+        paths.push((name, path));
+    }
+
+    let included: HashSet<String> = files
+        .iter()
+        .flat_map(|(name, code)| preprocess::includes(name, code))
+        .collect();
+
+    let mut errors = Vec::new();
+    for name in files.keys().filter(|name| !included.contains(*name)) {
+        let label = format!("{}/{}", root, name);
+        let output = preprocess::expand(
+            name,
+            &BTreeMap::new(),
+            |path| {
+                files
+                    .get(path)
+                    .map(|code| Cow::Borrowed(code.as_str()))
+                    .ok_or_else(|| format!("{}: no such shader to include", path))
+            },
+            |message| message,
+        );
+        let checked = output.and_then(|output| {
+            // Line numbers refer to the expanded source once anything is
+            // included, so say so, and say where each label came from
+            match output.files.len() {
+                1 => check_shader(&label, &output.code).map_err(|(message, _)| message),
+                _ => check_shader(&format!("{} (expanded)", label), &output.code).map_err(
+                    |(mut message, labels)| {
+                        let files: Vec<_> = output
+                            .files
+                            .iter()
+                            .map(|file| format!("{}/{}", root, file))
+                            .collect();
+                        message.push_str(&preprocess::locate(
+                            &output.code,
+                            &files,
+                            &output.lines,
+                            labels
+                                .iter()
+                                .map(|(range, label)| (range.clone(), label.as_str())),
+                        ));
+                        message
+                    },
+                ),
+            }
+        });
+        match checked {
+            Result::Ok(module) => write_constants(constants, name, &module)?,
+            Err(message) => errors.push(format!("{}:\n{}", label, message)),
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("invalid shaders:\n\n{}", errors.join("\n"));
+    }
+    Ok(paths)
+}
+
+/// Writes `BUILTIN_FILES`, which compiles each of `files`, as returned by
+/// [`check_shaders`], into the binary under its name.
+fn write_builtin_files(out: &mut String, files: &[(String, PathBuf)]) -> Result<()> {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR")?);
+    writeln!(out, "/// Every shader under `res/`, keyed by asset path.")?;
+    writeln!(out, "pub const BUILTIN_FILES: &[(&str, &str)] = &[")?;
+    for (name, path) in files {
+        let path = manifest_dir.join(path);
+        writeln!(out, "    ({:?}, include_str!({:?})),", name, path)?;
+    }
+    writeln!(out, "];")?;
+    Ok(())
+}
+
+/// Validates `code` against what native backends support and against what
+/// WebGL2 supports, and translates each entry point to GLSL ES 3.0 the way
+/// wgpu does on the web, which catches features WebGL2 lacks.
+///
+/// Errors come with the byte ranges of `code` naga labelled, and what it
+/// said about each.
+fn check_shader(label: &str, code: &str) -> Result<Module, (String, Vec<Labelled>)> {
+    let module = wgsl::parse_str(code).map_err(|e| {
+        let labels = e
+            .labels()
+            .filter_map(|(span, label)| Some((span.to_range()?, label.to_string())));
+        (e.emit_to_string_with_path(code, label), labels.collect())
+    })?;
+    let validation_error = |backend: &str, e: WithSpan<ValidationError>| {
+        let labels = e
+            .spans()
+            .filter_map(|(span, label)| Some((span.to_range()?, label.clone())));
+        let message = format!("{}: {}", backend, e.emit_to_string_with_path(code, label));
+        (message, labels.collect())
+    };
+
+    Validator::new(ValidationFlags::all(), Capabilities::default())
+        .validate(&module)
+        .map_err(|e| validation_error("native", e))?;
+
+    let webgl_info = Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|e| validation_error("WebGL2", e))?;
+    let options = glsl::Options {
+        version: glsl::Version::Embedded {
+            version: 300,
+            is_webgl: true,
+        },
+        ..Default::default()
+    };
+    for entry_point in &module.entry_points {
+        let pipeline_options = glsl::PipelineOptions {
+            shader_stage: entry_point.stage,
+            entry_point: entry_point.name.clone(),
+            multiview: None,
+        };
+        let mut glsl = String::new();
+        glsl::Writer::new(
+            &mut glsl,
+            &module,
+            &webgl_info,
+            &options,
+            &pipeline_options,
+            BoundsCheckPolicies::default(),
+        )
+        .and_then(|mut writer| writer.write())
+        .map_err(|e| (format!("WebGL2: {}: {}", entry_point.name, e), Vec::new()))?;
+    }
+
+    Result::Ok(module)
+}
+
+/// Appends a module of constants for the entry points and bindings of the
+/// shader at `name`, so renaming one in WGSL breaks the build rather than the
+/// pipeline.
+fn write_constants(out: &mut String, name: &str, module: &Module) -> Result<()> {
+    let module_name = identifier(name.trim_end_matches(".wgsl")).to_lowercase();
+    writeln!(out, "/// Constants for `{}`.", name)?;
+    writeln!(out, "pub mod {} {{", module_name)?;
+    writeln!(out, "    pub const PATH: &str = {:?};", name)?;
+    for entry_point in &module.entry_points {
+        writeln!(
+            out,
+            "    pub const {}: &str = {:?};",
+            identifier(&entry_point.name).to_uppercase(),
+            entry_point.name
+        )?;
+    }
+    for (_, var) in module.global_variables.iter() {
+        if let (Some(var_name), Some(binding)) = (&var.name, &var.binding) {
+            let var_name = identifier(var_name).to_uppercase();
+            writeln!(
+                out,
+                "    pub const {}_GROUP: u32 = {};",
+                var_name, binding.group
+            )?;
+            writeln!(
+                out,
+                "    pub const {}_BINDING: u32 = {};",
+                var_name, binding.binding
+            )?;
+        }
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
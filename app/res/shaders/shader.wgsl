@@ -1,19 +1,18 @@
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
-}
+#include "common.wgsl"
 
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
+struct Camera {
+    view_proj: mat4x4<f32>,
 }
 
+@group(0) @binding(2)
+var<uniform> camera: Camera;
+
 @vertex
 fn vs_main(
     model: VertexInput
 ) -> VertexOutput {
     var out: VertexOutput;
-    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
     out.tex_coords = model.tex_coords;
     return out;
 }
@@ -28,4 +27,4 @@ fn fs_main(
     in: VertexOutput
 ) -> @location(0) vec4<f32> {
     return textureSample(tex_diffuse, smp_diffuse, in.tex_coords);
-}
\ No newline at end of file
+}
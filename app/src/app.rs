@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use winit::{dpi::PhysicalSize, event::WindowEvent};
+
+use crate::{engine::Engine, error::EngineError};
+
+/// A program driven by the runner in [`crate::run_app`]. The runner owns the
+/// window, the wasm canvas and the [`Engine`], and calls these hooks as the
+/// event loop turns, so each lesson only has to describe what it draws.
+///
+/// Every hook except [`App::render`] has a default that does nothing. Hooks
+/// fail with an [`EngineError`], which the runner uses to recover from a
+/// lost surface or device; wrap errors of the app's own in
+/// [`EngineError::App`].
+pub trait App {
+    /// Called after the engine has been created and before the first frame,
+    /// and again whenever the engine recovers from losing its device, so any
+    /// GPU resources the app owns can be recreated on the new one.
+    fn init(&mut self, _engine: &mut Engine) -> Result<(), EngineError> {
+        Ok(())
+    }
+
+    /// Advances the app's state by `dt`. The runner calls this at a fixed rate,
+    /// set by [`Config::update_rate`](crate::config::Config::update_rate),
+    /// regardless of how often frames are drawn. In on-demand mode, call
+    /// [`Engine::request_redraw`] whenever the change should be shown.
+    fn update(&mut self, _engine: &mut Engine, _dt: Duration) {}
+
+    /// Offers a window event to the app before the runner handles it. Return
+    /// `true` to consume the event and stop the runner's default handling.
+    fn input(&mut self, _engine: &mut Engine, _event: &WindowEvent) -> bool {
+        false
+    }
+
+    /// Draws a frame. `alpha` is how far, from 0 to 1, the frame falls between
+    /// the last update and the next, for interpolating moving objects.
+    fn render(&mut self, engine: &mut Engine, alpha: f32) -> Result<(), EngineError>;
+
+    /// Whether [`App::render`] draws nothing but the model and texture named by
+    /// the config, as [`Scene::from_config`](crate::renderer::Scene::from_config)
+    /// does. `--cpu` draws that scene instead of running the app, so it is
+    /// refused for apps that don't.
+    fn draws_config_scene(&self) -> bool {
+        false
+    }
+
+    /// Called after the engine has been resized to `new_size`.
+    fn resize(&mut self, _engine: &mut Engine, _new_size: PhysicalSize<u32>) {}
+}
@@ -0,0 +1,606 @@
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    hash::Hash,
+    io::{BufReader, Cursor},
+    marker::PhantomData,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Weak,
+    },
+};
+
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    Buffer, BufferUsages, Device, Queue, TextureFormat,
+};
+
+use crate::{
+    bake::TextureData,
+    engine::Mesh,
+    error::{Chain, EngineError},
+    resources::{load_model, load_string, load_texture_data},
+    texture::Texture,
+};
+
+/// Something the [`AssetServer`] can load from a path.
+///
+/// Loading happens in two steps: [`Asset::load`] reads and decodes the file
+/// away from the render loop, then [`Asset::upload`] turns the result into
+/// GPU resources on the thread that owns the device.
+pub trait Asset: 'static {
+    /// Options that change how the asset is imported. Loads of the same path
+    /// with different settings are cached separately.
+    type Settings: Clone + fmt::Debug + Default + Eq + Hash + Send + 'static;
+    /// The decoded, CPU-side form of the asset.
+    type Data: Send + 'static;
+    /// What handles to this asset resolve to once it has been uploaded.
+    type Loaded: 'static;
+
+    fn load(
+        path: String,
+        settings: Self::Settings,
+    ) -> impl Future<Output = Result<Self::Data, EngineError>>;
+
+    fn upload(
+        data: Self::Data,
+        settings: &Self::Settings,
+        server: &AssetServer,
+        device: &Device,
+        queue: &Queue,
+    ) -> Result<Self::Loaded, EngineError>;
+
+    /// Writes reloaded `data` into the GPU resources of `existing`, so that
+    /// anything already using them sees the change. Gives `data` back if it
+    /// doesn't fit, in which case it's uploaded as a new value instead.
+    fn update_in_place(
+        existing: &Self::Loaded,
+        data: Self::Data,
+        queue: &Queue,
+    ) -> Result<(), Self::Data> {
+        let _ = (existing, queue);
+        Err(data)
+    }
+
+    /// The file the asset at `path` is read from, for matching changed files
+    /// to assets.
+    fn source_file(path: &str) -> &str {
+        path
+    }
+}
+
+/// Whether the asset behind a handle can be used yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    /// Holds the error, formatted with its sources.
+    Failed(String),
+}
+
+struct HandleId(u64);
+
+/// A reference-counted reference to an asset loaded by an [`AssetServer`].
+/// The asset, and its GPU resources, are freed on the
+/// [`AssetServer::update`] after the last handle to it is dropped.
+pub struct Handle<T: Asset> {
+    id: Arc<HandleId>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Asset> Handle<T> {
+    fn new(id: Arc<HandleId>) -> Self {
+        Self {
+            id,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Asset> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.id.clone())
+    }
+}
+
+impl<T: Asset> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id.0 == other.id.0
+    }
+}
+
+impl<T: Asset> Eq for Handle<T> {}
+
+impl<T: Asset> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.id.0)
+    }
+}
+
+struct Entry<T: Asset> {
+    path: String,
+    settings: T::Settings,
+    handle: Weak<HandleId>,
+    state: LoadState,
+    value: Option<Rc<T::Loaded>>,
+}
+
+/// Every asset of one type.
+struct Assets<T: Asset> {
+    entries: HashMap<u64, Entry<T>>,
+    by_key: HashMap<(String, T::Settings), u64>,
+}
+
+impl<T: Asset> Default for Assets<T> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_key: HashMap::new(),
+        }
+    }
+}
+
+/// The type-erased operations [`AssetServer::update`] needs on every
+/// [`Assets`].
+trait Storage {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// Frees the assets nothing holds a handle to any more.
+    fn collect(&mut self);
+    /// Marks every asset as loading again and starts loading it.
+    fn restart(&mut self, sender: &Sender<Loaded>);
+    /// Starts loading every asset read from `file` again, keeping the current
+    /// values until the new ones arrive. Returns how many there were.
+    fn reload_file(&mut self, file: &str, sender: &Sender<Loaded>) -> usize;
+}
+
+impl<T: Asset> Storage for Assets<T> {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn collect(&mut self) {
+        let by_key = &mut self.by_key;
+        self.entries.retain(|id, entry| {
+            let alive = entry.handle.strong_count() > 0;
+            if !alive {
+                log::debug!("Freeing {}", entry.path);
+                // The key may already name a newer entry, if the asset was
+                // loaded again after its last handle was dropped
+                let key = (entry.path.clone(), entry.settings.clone());
+                if by_key.get(&key) == Some(id) {
+                    by_key.remove(&key);
+                }
+            }
+            alive
+        });
+    }
+
+    fn restart(&mut self, sender: &Sender<Loaded>) {
+        for (id, entry) in &mut self.entries {
+            entry.state = LoadState::Loading;
+            entry.value = None;
+            spawn::<T>(sender, *id, entry.path.clone(), entry.settings.clone());
+        }
+    }
+
+    fn reload_file(&mut self, file: &str, sender: &Sender<Loaded>) -> usize {
+        let mut count = 0;
+        for (id, entry) in &self.entries {
+            if T::source_file(&entry.path) == file {
+                spawn::<T>(sender, *id, entry.path.clone(), entry.settings.clone());
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+/// A finished load, sent back from wherever the loading happened.
+struct Loaded {
+    type_id: TypeId,
+    id: u64,
+    result: Box<dyn Any + Send>,
+    finish: fn(&AssetServer, u64, Box<dyn Any + Send>, &Device, &Queue),
+}
+
+/// Loads assets in the background and hands out [`Handle`]s to them.
+///
+/// Loading the same path with the same settings twice returns the same
+/// handle for as long as one is alive. Decoding happens on another thread on
+/// native and on the browser's event loop on wasm, and the results are
+/// uploaded to the GPU by [`AssetServer::update`], which the engine calls at
+/// the start of every frame. Until then, [`AssetServer::state`] reports
+/// [`LoadState::Loading`] and the renderer can draw
+/// [`AssetServer::placeholder_texture`] instead.
+pub struct AssetServer {
+    next_id: AtomicU64,
+    placeholder: Rc<Texture>,
+    receiver: Receiver<Loaded>,
+    sender: Sender<Loaded>,
+    storages: RefCell<HashMap<TypeId, Box<dyn Storage>>>,
+}
+
+impl AssetServer {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            next_id: AtomicU64::new(0),
+            placeholder: Rc::new(Self::create_placeholder(device, queue)),
+            receiver,
+            sender,
+            storages: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// A 2x2 magenta and black checkerboard, which is hard to mistake for a
+    /// real texture.
+    fn create_placeholder(device: &Device, queue: &Queue) -> Texture {
+        let magenta = [255, 0, 255, 255];
+        let black = [0, 0, 0, 255];
+        let data = TextureData {
+            width: 2,
+            height: 2,
+            levels: vec![[magenta, black, black, magenta].concat()],
+        };
+        Texture::from_texture_data(
+            device,
+            queue,
+            &data,
+            TextureFormat::Rgba8UnormSrgb,
+            Some("AssetServer placeholder"),
+        )
+    }
+
+    pub fn placeholder_texture(&self) -> Rc<Texture> {
+        self.placeholder.clone()
+    }
+
+    /// Starts loading `path` with default settings.
+    pub fn load<T: Asset>(&self, path: &str) -> Handle<T> {
+        self.load_with(path, T::Settings::default())
+    }
+
+    /// Starts loading `path` with `settings`, unless it is already loaded or
+    /// loading, in which case the existing handle is returned.
+    pub fn load_with<T: Asset>(&self, path: &str, settings: T::Settings) -> Handle<T> {
+        let mut storages = self.storages.borrow_mut();
+        let assets = storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::<Assets<T>>::default())
+            .as_any_mut()
+            .downcast_mut::<Assets<T>>()
+            .unwrap();
+
+        let key = (path.to_string(), settings);
+        if let Some(id) = assets.by_key.get(&key) {
+            if let Some(handle) = assets.entries[id].handle.upgrade() {
+                return Handle::new(handle);
+            }
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let handle = Arc::new(HandleId(id));
+        let (path, settings) = key;
+        assets.entries.insert(
+            id,
+            Entry {
+                path: path.clone(),
+                settings: settings.clone(),
+                handle: Arc::downgrade(&handle),
+                state: LoadState::Loading,
+                value: None,
+            },
+        );
+        assets.by_key.insert((path.clone(), settings.clone()), id);
+        spawn::<T>(&self.sender, id, path, settings);
+        Handle::new(handle)
+    }
+
+    /// Uploads everything that has finished loading since the last call, and
+    /// frees assets that are no longer referenced.
+    pub fn update(&self, device: &Device, queue: &Queue) {
+        while let Ok(loaded) = self.receiver.try_recv() {
+            if self.storages.borrow().contains_key(&loaded.type_id) {
+                (loaded.finish)(self, loaded.id, loaded.result, device, queue);
+            }
+        }
+        for storage in self.storages.borrow_mut().values_mut() {
+            storage.collect();
+        }
+    }
+
+    fn finish<T: Asset>(
+        &self,
+        id: u64,
+        result: Box<dyn Any + Send>,
+        device: &Device,
+        queue: &Queue,
+    ) {
+        let result = *result
+            .downcast::<Result<T::Data, EngineError>>()
+            .expect("a load finished with the wrong type");
+        let Some(settings) = self.with_entry::<T, _>(id, |entry| entry.settings.clone()) else {
+            // Every handle was dropped while it loaded
+            return;
+        };
+        let existing = self
+            .with_entry::<T, _>(id, |entry| entry.value.clone())
+            .flatten();
+        // Uploading may load other assets, so the storage can't be borrowed
+        let result = result.and_then(|data| {
+            let data = match existing {
+                Some(existing) => match T::update_in_place(&existing, data, queue) {
+                    Ok(()) => return Ok(None),
+                    Err(data) => data,
+                },
+                None => data,
+            };
+            T::upload(data, &settings, self, device, queue).map(Some)
+        });
+        self.with_entry::<T, _>(id, |entry| match result {
+            Ok(value) => {
+                entry.state = LoadState::Loaded;
+                if let Some(value) = value {
+                    entry.value = Some(Rc::new(value));
+                }
+            }
+            // A failed reload leaves the last good value in place
+            Err(e) if entry.value.is_some() => {
+                log::error!("Failed to reload {}: {}", entry.path, e);
+            }
+            Err(e) => {
+                log::error!("Failed to load {}: {}", entry.path, e);
+                entry.state = LoadState::Failed(Chain(&e).to_string());
+            }
+        });
+    }
+
+    fn with_entry<T: Asset, R>(&self, id: u64, f: impl FnOnce(&mut Entry<T>) -> R) -> Option<R> {
+        let mut storages = self.storages.borrow_mut();
+        let assets = storages
+            .get_mut(&TypeId::of::<T>())?
+            .as_any_mut()
+            .downcast_mut::<Assets<T>>()?;
+        assets.entries.get_mut(&id).map(f)
+    }
+
+    pub fn state<T: Asset>(&self, handle: &Handle<T>) -> LoadState {
+        self.with_entry::<T, _>(handle.id.0, |entry| entry.state.clone())
+            .unwrap_or(LoadState::Loading)
+    }
+
+    /// The asset behind `handle`, if it has finished loading.
+    pub fn get<T: Asset>(&self, handle: &Handle<T>) -> Option<Rc<T::Loaded>> {
+        self.with_entry::<T, _>(handle.id.0, |entry| entry.value.clone())
+            .flatten()
+    }
+
+    /// The texture behind `handle`, or the placeholder if it hasn't loaded or
+    /// failed to.
+    pub fn texture_or_placeholder(&self, handle: &Handle<Texture>) -> Rc<Texture> {
+        self.get(handle)
+            .unwrap_or_else(|| self.placeholder_texture())
+    }
+
+    /// Loads every asset read from `file` again, for when it changes on disk.
+    /// Textures and meshes that keep their size are updated in place; others
+    /// get new values, which [`AssetServer::get`] returns once they're
+    /// uploaded. Returns whether any asset was read from `file`.
+    pub fn reload_file(&self, file: &str) -> bool {
+        let mut count = 0;
+        for storage in self.storages.borrow_mut().values_mut() {
+            count += storage.reload_file(file, &self.sender);
+        }
+        count > 0
+    }
+
+    /// Loads every asset again, for after the device they were uploaded to
+    /// has been lost. Handles stay valid.
+    pub fn reload(&mut self, device: &Device, queue: &Queue) {
+        self.placeholder = Rc::new(Self::create_placeholder(device, queue));
+        // Drop any loads that finished for the old device
+        while self.receiver.try_recv().is_ok() {}
+        for storage in self.storages.get_mut().values_mut() {
+            storage.restart(&self.sender);
+        }
+    }
+}
+
+/// Starts loading asset `id` in the background.
+fn spawn<T: Asset>(sender: &Sender<Loaded>, id: u64, path: String, settings: T::Settings) {
+    let sender = sender.clone();
+    // Built where it runs, since resource loading futures aren't `Send`
+    let task = move || async move {
+        let result = T::load(path, settings).await;
+        let loaded = Loaded {
+            type_id: TypeId::of::<T>(),
+            id,
+            result: Box::new(result),
+            finish: AssetServer::finish::<T>,
+        };
+        // The server may have been dropped while this was loading
+        sender.send(loaded).ok();
+    };
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            wasm_bindgen_futures::spawn_local(task());
+        } else {
+            std::thread::spawn(move || pollster::block_on(task()));
+        }
+    }
+}
+
+/// Settings for loading a [`Texture`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TextureSettings {
+    /// Store the texels as linear values rather than sRGB, as normal maps
+    /// and other non-color data need.
+    pub linear: bool,
+}
+
+impl Asset for Texture {
+    type Settings = TextureSettings;
+    type Data = TextureData;
+    type Loaded = Texture;
+
+    async fn load(path: String, _: TextureSettings) -> Result<TextureData, EngineError> {
+        load_texture_data(&path).await
+    }
+
+    fn upload(
+        data: TextureData,
+        settings: &TextureSettings,
+        _: &AssetServer,
+        device: &Device,
+        queue: &Queue,
+    ) -> Result<Texture, EngineError> {
+        let format = if settings.linear {
+            TextureFormat::Rgba8Unorm
+        } else {
+            TextureFormat::Rgba8UnormSrgb
+        };
+        Ok(Texture::from_texture_data(
+            device,
+            queue,
+            &data,
+            format,
+            Some("AssetServer texture"),
+        ))
+    }
+
+    fn update_in_place(
+        existing: &Texture,
+        data: TextureData,
+        queue: &Queue,
+    ) -> Result<(), TextureData> {
+        if existing.write_texture_data(queue, &data) {
+            Ok(())
+        } else {
+            Err(data)
+        }
+    }
+}
+
+/// A [`Mesh`] uploaded to the GPU.
+pub struct MeshBuffers {
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    pub index_count: u32,
+}
+
+impl Asset for Mesh {
+    type Settings = ();
+    type Data = Mesh;
+    type Loaded = MeshBuffers;
+
+    async fn load(path: String, _: ()) -> Result<Mesh, EngineError> {
+        load_model(&path).await
+    }
+
+    fn upload(
+        mesh: Mesh,
+        _: &(),
+        _: &AssetServer,
+        device: &Device,
+        _: &Queue,
+    ) -> Result<MeshBuffers, EngineError> {
+        Ok(MeshBuffers {
+            vertex_buffer: device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("AssetServer vertex buffer"),
+                contents: bytemuck::cast_slice(&mesh.vertices),
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            }),
+            index_buffer: device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("AssetServer index buffer"),
+                contents: bytemuck::cast_slice(&mesh.indices),
+                usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
+            }),
+            index_count: mesh.indices.len() as u32,
+        })
+    }
+
+    fn update_in_place(existing: &MeshBuffers, mesh: Mesh, queue: &Queue) -> Result<(), Mesh> {
+        if mesh.write_buffers(queue, &existing.vertex_buffer, &existing.index_buffer) {
+            Ok(())
+        } else {
+            Err(mesh)
+        }
+    }
+}
+
+/// The parts of an OBJ material the engine understands.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    pub diffuse_color: [f32; 3],
+    /// Loaded through the same server, so it may still be loading after the
+    /// material itself has.
+    pub diffuse_texture: Option<Handle<Texture>>,
+}
+
+/// A material as read from an MTL file, before its textures are requested.
+pub struct MaterialData {
+    name: String,
+    diffuse_color: [f32; 3],
+    diffuse_texture: Option<String>,
+}
+
+impl Asset for Material {
+    type Settings = ();
+    type Data = MaterialData;
+    type Loaded = Material;
+
+    /// Loads a material from an MTL file. `path` picks the material by name
+    /// after a `#`, as in `models/crate.mtl#Wood`, and otherwise takes the
+    /// first one.
+    async fn load(path: String, _: ()) -> Result<MaterialData, EngineError> {
+        let (file_name, name) = match path.split_once('#') {
+            Some((file_name, name)) => (file_name, Some(name)),
+            None => (path.as_str(), None),
+        };
+        let text = load_string(file_name).await?;
+        let (materials, _) = tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(text)))
+            .map_err(|e| EngineError::decode_failed(file_name, e))?;
+        let material = materials
+            .into_iter()
+            .find(|m| name.is_none_or(|name| m.name == name))
+            .ok_or_else(|| {
+                EngineError::decode_failed(file_name, format!("no material named {:?}", name))
+            })?;
+
+        // Textures are named relative to the MTL file
+        let dir = file_name.rsplit_once('/').map_or("", |(dir, _)| dir);
+        Ok(MaterialData {
+            name: material.name,
+            diffuse_color: material.diffuse.unwrap_or([1.0, 1.0, 1.0]),
+            diffuse_texture: material.diffuse_texture.map(|texture| match dir {
+                "" => texture,
+                dir => format!("{}/{}", dir, texture),
+            }),
+        })
+    }
+
+    fn upload(
+        data: MaterialData,
+        _: &(),
+        server: &AssetServer,
+        _: &Device,
+        _: &Queue,
+    ) -> Result<Material, EngineError> {
+        Ok(Material {
+            name: data.name,
+            diffuse_color: data.diffuse_color,
+            diffuse_texture: data.diffuse_texture.map(|path| server.load(&path)),
+        })
+    }
+
+    fn source_file(path: &str) -> &str {
+        path.split_once('#')
+            .map_or(path, |(file_name, _)| file_name)
+    }
+}
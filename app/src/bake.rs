@@ -0,0 +1,483 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{BufReader, Cursor},
+};
+
+use image::RgbaImage;
+
+/// Bumped whenever the processing or the formats below change, so assets
+/// baked by an older build script are baked again.
+pub const VERSION: u32 = 1;
+
+/// Where baked assets live, relative to the asset root.
+pub const DIR: &str = "processed";
+
+/// The name of the manifest within [`DIR`].
+pub const MANIFEST: &str = "manifest.txt";
+
+const TEXTURE_MAGIC: &[u8; 4] = b"TRTX";
+const MESH_MAGIC: &[u8; 4] = b"TRMS";
+
+/// What a source asset is baked into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Texture,
+    Mesh,
+}
+
+impl Kind {
+    /// Guesses the kind of the source asset at `path` from its extension.
+    pub fn of(path: &str) -> Option<Self> {
+        let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" | "jpg" | "jpeg" | "bmp" | "tga" => Some(Kind::Texture),
+            "obj" | "gltf" | "glb" => Some(Kind::Mesh),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Kind::Texture => "tex",
+            Kind::Mesh => "mesh",
+        }
+    }
+}
+
+/// The asset path of the baked form of `source`.
+pub fn baked_path(source: &str, kind: Kind) -> String {
+    format!("{}/{}.{}", DIR, source, kind.extension())
+}
+
+/// FNV-1a, which is stable across builds and platforms, unlike the standard
+/// library's hashers.
+pub fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64 ^ u64::from(VERSION);
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// An sRGB RGBA8 texture along with every mip level down to 1x1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    /// Level 0 first. Each level is tightly packed RGBA8.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    /// Builds the mip chain for `image`, averaging in linear space so that
+    /// smaller levels don't darken.
+    pub fn from_image(image: &RgbaImage) -> Self {
+        let (width, height) = image.dimensions();
+        let mut levels = vec![image.as_raw().clone()];
+        let (mut w, mut h) = (width, height);
+        while w > 1 || h > 1 {
+            let next = downsample(levels.last().unwrap(), w, h);
+            w = (w / 2).max(1);
+            h = (h / 2).max(1);
+            levels.push(next);
+        }
+        Self {
+            width,
+            height,
+            levels,
+        }
+    }
+
+    /// The number of levels in a full mip chain, down to 1x1.
+    pub fn full_level_count(&self) -> usize {
+        (u32::BITS - self.width.max(self.height).max(1).leading_zeros()) as usize
+    }
+
+    /// The size of mip level `level`, which is less than
+    /// [`TextureData::full_level_count`].
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(TEXTURE_MAGIC);
+        for value in [VERSION, self.width, self.height, self.levels.len() as u32] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        for level in &self.levels {
+            out.extend_from_slice(level);
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut reader = Reader::new(bytes, TEXTURE_MAGIC)?;
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let level_count = reader.u32()?;
+        let mut texture = Self {
+            width,
+            height,
+            levels: Vec::new(),
+        };
+        if level_count as usize > texture.full_level_count() {
+            return Err(FormatError(format!(
+                "{} levels is more than a {}x{} texture has",
+                level_count, width, height
+            )));
+        }
+        for level in 0..level_count as usize {
+            let (w, h) = texture.level_size(level);
+            let size = (w as usize)
+                .checked_mul(h as usize)
+                .and_then(|texels| texels.checked_mul(4))
+                .ok_or_else(|| FormatError(format!("a {}x{} level is too big", w, h)))?;
+            texture.levels.push(reader.bytes(size)?.to_vec());
+        }
+        reader.finish()?;
+        Ok(texture)
+    }
+}
+
+/// Halves a level in each dimension, averaging each 2x2 block.
+fn downsample(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (w, h) = ((width / 2).max(1), (height / 2).max(1));
+    let texel = |x: u32, y: u32, c: usize| -> f32 {
+        let (x, y) = (x.min(width - 1), y.min(height - 1));
+        let value = pixels[((y * width + x) * 4) as usize + c];
+        // Alpha is linear already
+        if c == 3 {
+            f32::from(value) / 255.0
+        } else {
+            srgb_to_linear(value)
+        }
+    };
+    let mut out = Vec::with_capacity((w * h * 4) as usize);
+    for y in 0..h {
+        for x in 0..w {
+            for c in 0..4 {
+                let sum = texel(2 * x, 2 * y, c)
+                    + texel(2 * x + 1, 2 * y, c)
+                    + texel(2 * x, 2 * y + 1, c)
+                    + texel(2 * x + 1, 2 * y + 1, c);
+                let average = sum / 4.0;
+                out.push(if c == 3 {
+                    (average * 255.0).round() as u8
+                } else {
+                    linear_to_srgb(average)
+                });
+            }
+        }
+    }
+    out
+}
+
+/// Decodes an sRGB-encoded channel to a linear value from 0 to 1.
+pub fn srgb_to_linear(value: u8) -> f32 {
+    let v = f32::from(value) / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear value from 0 to 1 as an sRGB channel, clamping it first.
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let v = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// An indexed triangle list. Each vertex is a position followed by texture
+/// coordinates, matching `ModelVertex`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MeshData {
+    pub vertices: Vec<[f32; 5]>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    /// Reads every mesh in an OBJ file. Materials are ignored.
+    pub fn from_obj(text: &str) -> Result<Self, FormatError> {
+        let mut reader = BufReader::new(Cursor::new(text));
+        let (models, _) = tobj::load_obj_buf(
+            &mut reader,
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+            |_| Err(tobj::LoadError::OpenFileFailed),
+        )
+        .map_err(|e| FormatError(e.to_string()))?;
+
+        let mut data = Self::default();
+        for model in &models {
+            let mesh = &model.mesh;
+            let base = data.vertices.len() as u32;
+            for i in 0..mesh.positions.len() / 3 {
+                // OBJ puts v = 0 at the bottom of the image, wgpu at the top
+                let [u, v] = if mesh.texcoords.is_empty() {
+                    [0.0, 0.0]
+                } else {
+                    [mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1]]
+                };
+                let p = &mesh.positions[i * 3..i * 3 + 3];
+                data.vertices.push([p[0], p[1], p[2], u, v]);
+            }
+            data.indices.extend(mesh.indices.iter().map(|i| base + i));
+        }
+        Ok(data)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MESH_MAGIC);
+        for value in [
+            VERSION,
+            self.vertices.len() as u32,
+            self.indices.len() as u32,
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in self.vertices.iter().flatten() {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        for index in &self.indices {
+            out.extend_from_slice(&index.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut reader = Reader::new(bytes, MESH_MAGIC)?;
+        let vertex_count = reader.u32()? as usize;
+        let index_count = reader.u32()? as usize;
+        // Check the counts against the data before trusting them with an
+        // allocation
+        let size = vertex_count
+            .checked_mul(20)
+            .zip(index_count.checked_mul(4))
+            .and_then(|(vertices, indices)| vertices.checked_add(indices));
+        if size != Some(reader.remaining()) {
+            return Err(FormatError(format!(
+                "{} vertices and {} indices don't match the {} bytes of data",
+                vertex_count,
+                index_count,
+                reader.remaining()
+            )));
+        }
+        let mut data = Self {
+            vertices: Vec::with_capacity(vertex_count),
+            indices: Vec::with_capacity(index_count),
+        };
+        for _ in 0..vertex_count {
+            let mut vertex = [0.0; 5];
+            for value in &mut vertex {
+                *value = f32::from_bits(reader.u32()?);
+            }
+            data.vertices.push(vertex);
+        }
+        for _ in 0..index_count {
+            let index = reader.u32()?;
+            if index as usize >= vertex_count {
+                return Err(FormatError(format!("index {} is out of range", index)));
+            }
+            data.indices.push(index);
+        }
+        reader.finish()?;
+        Ok(data)
+    }
+}
+
+/// One baked asset, as recorded in the manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub hash: u64,
+    pub baked: String,
+}
+
+/// Every baked asset, keyed by the asset path of its source. Written by the
+/// build script next to the baked assets, and read back on the next build to
+/// skip sources that haven't changed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Parses a manifest, skipping any lines it doesn't understand.
+    pub fn parse(text: &str) -> Self {
+        let entries = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let source = fields.next()?;
+                let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let baked = fields.next()?;
+                Some((
+                    source.to_string(),
+                    ManifestEntry {
+                        hash,
+                        baked: baked.to_string(),
+                    },
+                ))
+            })
+            .collect();
+        Self { entries }
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# source\thash\tbaked (version {})", VERSION)?;
+        for (source, entry) in &self.entries {
+            writeln!(f, "{}\t{:016x}\t{}", source, entry.hash, entry.baked)?;
+        }
+        Ok(())
+    }
+}
+
+/// A baked asset that couldn't be read or written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormatError(pub String);
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Reader<'b> {
+    /// Checks the magic number and version at the start of `bytes`.
+    fn new(bytes: &'b [u8], magic: &[u8; 4]) -> Result<Self, FormatError> {
+        let mut reader = Self { bytes };
+        if reader.bytes(4)? != magic {
+            return Err(FormatError("not a baked asset of the right kind".into()));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(FormatError(format!(
+                "baked with version {}, expected {}",
+                version, VERSION
+            )));
+        }
+        Ok(reader)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'b [u8], FormatError> {
+        if self.bytes.len() < len {
+            return Err(FormatError("unexpected end of data".into()));
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn finish(self) -> Result<(), FormatError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(FormatError("trailing data".into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texture_header(width: u32, height: u32, level_count: u32) -> Vec<u8> {
+        let mut bytes = TEXTURE_MAGIC.to_vec();
+        for value in [VERSION, width, height, level_count] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn textures_round_trip() {
+        let image = RgbaImage::from_fn(5, 3, |x, y| image::Rgba([x as u8, y as u8, 7, 255]));
+        let texture = TextureData::from_image(&image);
+        assert_eq!(texture.levels.len(), texture.full_level_count());
+        assert_eq!(TextureData::decode(&texture.encode()), Ok(texture));
+    }
+
+    #[test]
+    fn corrupt_textures_are_errors() {
+        for (width, height, level_count) in [
+            (4, 4, 4),
+            (4, 1, 40),
+            (1 << 31, 1, 33),
+            (u32::MAX, u32::MAX, 32),
+            (0, 0, 2),
+        ] {
+            let result = TextureData::decode(&texture_header(width, height, level_count));
+            assert!(
+                result.is_err(),
+                "{}x{} with {} levels",
+                width,
+                height,
+                level_count
+            );
+        }
+    }
+
+    fn mesh() -> MeshData {
+        MeshData {
+            vertices: vec![[0.0, 1.0, 2.0, 0.5, 0.25], [3.0, 4.0, 5.0, 1.0, 0.0]],
+            indices: vec![0, 1, 1],
+        }
+    }
+
+    #[test]
+    fn meshes_round_trip() {
+        assert_eq!(MeshData::decode(&mesh().encode()), Ok(mesh()));
+    }
+
+    #[test]
+    fn corrupt_meshes_are_errors() {
+        let bytes = mesh().encode();
+        let with_counts = |vertices: u32, indices: u32| {
+            let mut bytes = bytes.clone();
+            bytes[8..12].copy_from_slice(&vertices.to_le_bytes());
+            bytes[12..16].copy_from_slice(&indices.to_le_bytes());
+            bytes
+        };
+        let mut out_of_range = bytes.clone();
+        let last = out_of_range.len() - 4;
+        out_of_range[last..].copy_from_slice(&2u32.to_le_bytes());
+        for (name, bytes) in [
+            ("truncated", bytes[..bytes.len() - 1].to_vec()),
+            ("too many vertices", with_counts(u32::MAX, 3)),
+            ("too many indices", with_counts(2, u32::MAX)),
+            ("too few indices", with_counts(2, 2)),
+            ("out of range", out_of_range),
+        ] {
+            assert!(MeshData::decode(&bytes).is_err(), "{}", name);
+        }
+    }
+}
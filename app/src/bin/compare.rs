@@ -0,0 +1,78 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use tinyrenderer_wgpu::compare::{compare, diff_image, Thresholds};
+
+/// Compares two images and reports RMSE, PSNR, SSIM and max channel error,
+/// exiting with a non-zero status if any threshold is exceeded.
+#[derive(Debug, Parser)]
+struct Args {
+    /// The expected image
+    expected: PathBuf,
+    /// The image to check against the expected one
+    actual: PathBuf,
+    /// Write a false-color diff image to this path
+    #[arg(long)]
+    diff: Option<PathBuf>,
+    /// Channel error that maps to full red in the diff image
+    #[arg(long, default_value_t = 32)]
+    diff_scale: u8,
+    #[arg(long, default_value_t = Thresholds::default().max_rmse)]
+    max_rmse: f64,
+    #[arg(long, default_value_t = Thresholds::default().min_psnr)]
+    min_psnr: f64,
+    #[arg(long, default_value_t = Thresholds::default().min_ssim)]
+    min_ssim: f64,
+    #[arg(long, default_value_t = Thresholds::default().max_channel_error)]
+    max_channel_error: u8,
+    /// Only accept bit-identical images, ignoring the other thresholds
+    #[arg(long)]
+    exact: bool,
+}
+
+fn run(args: &Args) -> Result<bool> {
+    let expected = image::open(&args.expected)
+        .with_context(|| format!("Failed to open {}", args.expected.display()))?
+        .to_rgba8();
+    let actual = image::open(&args.actual)
+        .with_context(|| format!("Failed to open {}", args.actual.display()))?
+        .to_rgba8();
+
+    let metrics = compare(&expected, &actual)?;
+    println!("{}", metrics);
+
+    if let Some(path) = &args.diff {
+        diff_image(&expected, &actual, args.diff_scale)?
+            .save(path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+
+    let thresholds = if args.exact {
+        Thresholds::EXACT
+    } else {
+        Thresholds {
+            max_rmse: args.max_rmse,
+            min_psnr: args.min_psnr,
+            min_ssim: args.min_ssim,
+            max_channel_error: args.max_channel_error,
+        }
+    };
+    let failures = metrics.failures(&thresholds);
+    for failure in &failures {
+        println!("FAIL: {}", failure);
+    }
+    Ok(failures.is_empty())
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("{:#}", e);
+            ExitCode::from(2)
+        }
+    }
+}
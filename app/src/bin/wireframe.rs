@@ -0,0 +1,129 @@
+use std::{fs, path::PathBuf, process::ExitCode};
+
+use anyhow::{Context, Result};
+use clap::{Parser, ValueEnum};
+use image::{DynamicImage, Rgba, RgbaImage};
+use tinyrenderer_wgpu::{
+    bake::MeshData,
+    cpu::{
+        wireframe::{draw_wireframe, fit_orthographic},
+        Cap, Stroke,
+    },
+    engine::Mesh,
+    tga,
+};
+
+/// Draws the edges of an OBJ model's triangles from the front, scaled to fit,
+/// and saves them as a PNG or TGA. Runs entirely on the CPU.
+///
+/// With an opaque background, TGAs are written as RGB with tinyrenderer's
+/// writer, so they can be compared byte for byte with its output.
+#[derive(Debug, Parser)]
+struct Args {
+    /// The OBJ model to draw
+    model: PathBuf,
+    /// The image to write; the format is picked from the extension
+    output: PathBuf,
+    #[arg(long, default_value_t = 800)]
+    width: u32,
+    #[arg(long, default_value_t = 800)]
+    height: u32,
+    #[arg(long, value_enum, default_value_t = LineArg::Bresenham)]
+    line: LineArg,
+    /// Width in pixels of thick lines
+    #[arg(long, default_value_t = 2.0)]
+    thickness: f32,
+    /// How thick lines end
+    #[arg(long, value_enum, default_value_t = CapArg::Round)]
+    cap: CapArg,
+    /// Line color as RRGGBB or RRGGBBAA hex
+    #[arg(long, default_value = "ffffff", value_parser = parse_color)]
+    color: Rgba<u8>,
+    /// Background color as RRGGBB or RRGGBBAA hex
+    #[arg(long, default_value = "000000", value_parser = parse_color)]
+    background: Rgba<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum LineArg {
+    Bresenham,
+    Wu,
+    Thick,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CapArg {
+    Butt,
+    Square,
+    Round,
+}
+
+impl From<CapArg> for Cap {
+    fn from(value: CapArg) -> Self {
+        match value {
+            CapArg::Butt => Cap::Butt,
+            CapArg::Square => Cap::Square,
+            CapArg::Round => Cap::Round,
+        }
+    }
+}
+
+fn parse_color(value: &str) -> Result<Rgba<u8>, String> {
+    let value = value.trim_start_matches('#');
+    let channel = |i: usize| {
+        value
+            .get(i * 2..i * 2 + 2)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+    };
+    match (value.len(), channel(0), channel(1), channel(2)) {
+        (6 | 8, Some(r), Some(g), Some(b)) => {
+            let a = if value.len() == 8 {
+                channel(3)
+            } else {
+                Some(255)
+            };
+            a.map(|a| Rgba([r, g, b, a]))
+                .ok_or_else(|| format!("{} isn't a hex color", value))
+        }
+        _ => Err(format!("{} isn't a hex color", value)),
+    }
+}
+
+fn run(args: &Args) -> Result<()> {
+    let text = fs::read_to_string(&args.model)
+        .with_context(|| format!("Failed to read {}", args.model.display()))?;
+    let mesh = Mesh::from(
+        MeshData::from_obj(&text)
+            .with_context(|| format!("Failed to parse {}", args.model.display()))?,
+    );
+
+    let stroke = match args.line {
+        LineArg::Bresenham => Stroke::Aliased,
+        LineArg::Wu => Stroke::AntiAliased,
+        LineArg::Thick => Stroke::Thick {
+            width: args.thickness,
+            cap: args.cap.into(),
+        },
+    };
+    let mut image = RgbaImage::from_pixel(args.width, args.height, args.background);
+    let transform = fit_orthographic(&mesh, args.width as f32 / args.height as f32);
+    draw_wireframe(&mut image, &mesh, transform, args.color, stroke);
+    let image = if args.background[3] == u8::MAX {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(image).to_rgb8())
+    } else {
+        DynamicImage::ImageRgba8(image)
+    };
+    tga::save(&image, &args.output)
+        .with_context(|| format!("Failed to write {}", args.output.display()))
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            ExitCode::from(1)
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt;
+
+use wgpu::{
+    Adapter, AdapterInfo, CompositeAlphaMode, DownlevelCapabilities, Features, Limits, PresentMode,
+    SurfaceCapabilities, TextureFormat,
+};
+
+use crate::error::EngineError;
+
+/// Ordered lists of the surface settings the engine would like to use. For
+/// each setting, the first entry the surface supports wins; if none are
+/// supported the engine falls back to something the surface does support and
+/// logs a warning.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SurfacePreferences {
+    pub formats: Vec<TextureFormat>,
+    pub present_modes: Vec<PresentMode>,
+    pub alpha_modes: Vec<CompositeAlphaMode>,
+}
+
+impl Default for SurfacePreferences {
+    fn default() -> Self {
+        Self {
+            formats: vec![TextureFormat::Bgra8UnormSrgb, TextureFormat::Rgba8UnormSrgb],
+            present_modes: vec![PresentMode::Fifo],
+            alpha_modes: vec![CompositeAlphaMode::Opaque, CompositeAlphaMode::Auto],
+        }
+    }
+}
+
+/// The surface settings chosen from a [`SurfacePreferences`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SurfaceChoice {
+    pub format: TextureFormat,
+    pub present_mode: PresentMode,
+    pub alpha_mode: CompositeAlphaMode,
+}
+
+/// Returns the first of `preferred` that is in `supported`.
+fn first_supported<T: Copy + PartialEq>(preferred: &[T], supported: &[T]) -> Option<T> {
+    preferred.iter().copied().find(|p| supported.contains(p))
+}
+
+impl SurfacePreferences {
+    /// Picks a format, present mode and alpha mode from `caps`. Formats fall
+    /// back to any sRGB format before settling for the first supported one.
+    /// The `Auto*` present and alpha modes are resolved by wgpu itself, so
+    /// they are always considered supported, and are the last resort if the
+    /// surface lists no alpha modes.
+    ///
+    /// Fails if the surface supports no formats at all, which is how wgpu
+    /// says the adapter can't present to it.
+    pub fn choose(&self, caps: &SurfaceCapabilities) -> Result<SurfaceChoice, EngineError> {
+        let Some(&first_format) = caps.formats.first() else {
+            return Err(EngineError::SurfaceConfig {
+                reason: "the adapter can't present to the surface".to_string(),
+                source: None,
+            });
+        };
+        let format = first_supported(&self.formats, &caps.formats).unwrap_or_else(|| {
+            let fallback = caps
+                .formats
+                .iter()
+                .copied()
+                .find(|f| f.is_srgb())
+                .unwrap_or(first_format);
+            log::warn!(
+                "None of the preferred formats {:?} are supported, using {:?}",
+                self.formats,
+                fallback
+            );
+            fallback
+        });
+
+        let present_mode = self
+            .present_modes
+            .iter()
+            .copied()
+            .find(|mode| {
+                matches!(mode, PresentMode::AutoVsync | PresentMode::AutoNoVsync)
+                    || caps.present_modes.contains(mode)
+            })
+            .unwrap_or_else(|| {
+                // Fifo is the only mode every surface is required to support
+                log::warn!(
+                    "None of the preferred present modes {:?} are supported, using {:?}",
+                    self.present_modes,
+                    PresentMode::Fifo
+                );
+                PresentMode::Fifo
+            });
+
+        let alpha_mode = self
+            .alpha_modes
+            .iter()
+            .copied()
+            .find(|mode| *mode == CompositeAlphaMode::Auto || caps.alpha_modes.contains(mode))
+            .unwrap_or_else(|| {
+                let fallback = caps
+                    .alpha_modes
+                    .first()
+                    .copied()
+                    .unwrap_or(CompositeAlphaMode::Auto);
+                log::warn!(
+                    "None of the preferred alpha modes {:?} are supported, using {:?}",
+                    self.alpha_modes,
+                    fallback
+                );
+                fallback
+            });
+
+        Ok(SurfaceChoice {
+            format,
+            present_mode,
+            alpha_mode,
+        })
+    }
+}
+
+/// What one adapter can do.
+#[derive(Clone, Debug)]
+pub struct AdapterReport {
+    pub info: AdapterInfo,
+    pub features: Features,
+    pub limits: Limits,
+    pub downlevel: DownlevelCapabilities,
+}
+
+impl AdapterReport {
+    pub fn new(adapter: &Adapter) -> Self {
+        Self {
+            info: adapter.get_info(),
+            features: adapter.features(),
+            limits: adapter.limits(),
+            downlevel: adapter.get_downlevel_capabilities(),
+        }
+    }
+}
+
+impl fmt::Display for AdapterReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let AdapterInfo {
+            name,
+            backend,
+            device_type,
+            driver,
+            driver_info,
+            ..
+        } = &self.info;
+        writeln!(f, "{} ({:?}, {:?})", name, backend, device_type)?;
+        if !driver.is_empty() || !driver_info.is_empty() {
+            writeln!(f, "    driver: {} {}", driver, driver_info)?;
+        }
+        writeln!(f, "    features: {:?}", self.features)?;
+        writeln!(
+            f,
+            "    max texture size: {}, max bind groups: {}, max samplers per stage: {}",
+            self.limits.max_texture_dimension_2d,
+            self.limits.max_bind_groups,
+            self.limits.max_samplers_per_shader_stage
+        )?;
+        write!(
+            f,
+            "    downlevel flags: {:?}, shader model: {:?}",
+            self.downlevel.flags, self.downlevel.shader_model
+        )
+    }
+}
+
+/// What the surface supports and which of those settings were chosen.
+#[derive(Clone, Debug)]
+pub struct SurfaceReport {
+    pub formats: Vec<TextureFormat>,
+    pub present_modes: Vec<PresentMode>,
+    pub alpha_modes: Vec<CompositeAlphaMode>,
+    pub chosen: SurfaceChoice,
+}
+
+/// Everything the engine learned about the available hardware while starting
+/// up, and the choices it made as a result.
+#[derive(Clone, Debug)]
+pub struct CapabilityReport {
+    /// Every adapter the instance could find. On wasm, adapters can't be
+    /// enumerated, so this only contains the selected one.
+    pub adapters: Vec<AdapterReport>,
+    pub selected: AdapterReport,
+    /// `None` for a headless engine.
+    pub surface: Option<SurfaceReport>,
+    pub sample_count: u32,
+}
+
+impl fmt::Display for CapabilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Adapters:")?;
+        for (i, adapter) in self.adapters.iter().enumerate() {
+            writeln!(f, "  [{}] {}", i, adapter)?;
+        }
+        writeln!(
+            f,
+            "Selected adapter: {} ({:?})",
+            self.selected.info.name, self.selected.info.backend
+        )?;
+        if let Some(surface) = &self.surface {
+            writeln!(f, "Surface formats: {:?}", surface.formats)?;
+            writeln!(f, "Surface present modes: {:?}", surface.present_modes)?;
+            writeln!(f, "Surface alpha modes: {:?}", surface.alpha_modes)?;
+            writeln!(
+                f,
+                "Chose format {:?}, present mode {:?}, alpha mode {:?}",
+                surface.chosen.format, surface.chosen.present_mode, surface.chosen.alpha_mode
+            )?;
+        }
+        write!(f, "MSAA samples: {}", self.sample_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surfaces_without_formats_are_errors() {
+        let caps = SurfaceCapabilities::default();
+        assert!(matches!(
+            SurfacePreferences::default().choose(&caps),
+            Err(EngineError::SurfaceConfig { .. })
+        ));
+    }
+
+    #[test]
+    fn unsupported_preferences_fall_back() {
+        let caps = SurfaceCapabilities {
+            formats: vec![TextureFormat::Rgba8Unorm, TextureFormat::Rgba16Float],
+            present_modes: vec![PresentMode::Mailbox],
+            alpha_modes: vec![],
+            ..Default::default()
+        };
+        let preferences = SurfacePreferences {
+            alpha_modes: vec![CompositeAlphaMode::PreMultiplied],
+            ..Default::default()
+        };
+        assert_eq!(
+            preferences.choose(&caps).unwrap(),
+            SurfaceChoice {
+                format: TextureFormat::Rgba8Unorm,
+                present_mode: PresentMode::Fifo,
+                alpha_mode: CompositeAlphaMode::Auto,
+            }
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use std::fmt;
+
+use image::{Rgba, RgbaImage};
+use thiserror::Error;
+
+/// Side length of the square windows used when computing SSIM.
+const SSIM_WINDOW: u32 = 8;
+/// Distance between the origins of neighboring SSIM windows.
+const SSIM_STRIDE: u32 = 4;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// The result of comparing two images of the same size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metrics {
+    /// Root mean squared error over all RGBA channels, in the range 0-255.
+    pub rmse: f64,
+    /// Peak signal-to-noise ratio in decibels; infinite for identical images.
+    pub psnr: f64,
+    /// Mean structural similarity of the luma channel, in the range -1-1.
+    pub ssim: f64,
+    /// Largest absolute difference seen in any single channel of any pixel.
+    pub max_channel_error: u8,
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rmse: {:.4}, psnr: {:.2} dB, ssim: {:.5}, max channel error: {}",
+            self.rmse, self.psnr, self.ssim, self.max_channel_error
+        )
+    }
+}
+
+/// Limits that a [`Metrics`] must be within for two images to be considered a
+/// match. The defaults tolerate the small rounding differences expected between
+/// two rasterizers, but not visibly different output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+    pub max_rmse: f64,
+    pub min_psnr: f64,
+    pub min_ssim: f64,
+    pub max_channel_error: u8,
+}
+
+impl Thresholds {
+    /// Thresholds that only accept bit-identical images.
+    pub const EXACT: Thresholds = Thresholds {
+        max_rmse: 0.0,
+        min_psnr: f64::INFINITY,
+        min_ssim: 1.0,
+        max_channel_error: 0,
+    };
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            max_rmse: 2.0,
+            min_psnr: 40.0,
+            min_ssim: 0.98,
+            max_channel_error: 16,
+        }
+    }
+}
+
+impl Metrics {
+    /// Returns a description of every threshold these metrics violate. An
+    /// empty list means the images match.
+    pub fn failures(&self, thresholds: &Thresholds) -> Vec<String> {
+        let mut failures = vec![];
+        if self.rmse > thresholds.max_rmse {
+            failures.push(format!(
+                "rmse {:.4} exceeds {:.4}",
+                self.rmse, thresholds.max_rmse
+            ));
+        }
+        if self.psnr < thresholds.min_psnr {
+            failures.push(format!(
+                "psnr {:.2} dB is below {:.2} dB",
+                self.psnr, thresholds.min_psnr
+            ));
+        }
+        if self.ssim < thresholds.min_ssim {
+            failures.push(format!(
+                "ssim {:.5} is below {:.5}",
+                self.ssim, thresholds.min_ssim
+            ));
+        }
+        if self.max_channel_error > thresholds.max_channel_error {
+            failures.push(format!(
+                "max channel error {} exceeds {}",
+                self.max_channel_error, thresholds.max_channel_error
+            ));
+        }
+        failures
+    }
+
+    pub fn passes(&self, thresholds: &Thresholds) -> bool {
+        self.failures(thresholds).is_empty()
+    }
+}
+
+/// The error for images that can't be compared because their sizes differ.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+#[error("Cannot compare a {}x{} image with a {}x{} image", .a.0, .a.1, .b.0, .b.1)]
+pub struct SizeMismatch {
+    pub a: (u32, u32),
+    pub b: (u32, u32),
+}
+
+pub type Result<T> = std::result::Result<T, SizeMismatch>;
+
+fn ensure_same_size(a: &RgbaImage, b: &RgbaImage) -> Result<()> {
+    if a.dimensions() != b.dimensions() {
+        return Err(SizeMismatch {
+            a: a.dimensions(),
+            b: b.dimensions(),
+        });
+    }
+    Ok(())
+}
+
+/// Computes every metric for a pair of images with matching dimensions.
+pub fn compare(a: &RgbaImage, b: &RgbaImage) -> Result<Metrics> {
+    let rmse = rmse(a, b)?;
+    Ok(Metrics {
+        rmse,
+        psnr: psnr_from_rmse(rmse),
+        ssim: ssim(a, b)?,
+        max_channel_error: max_channel_error(a, b)?,
+    })
+}
+
+pub fn rmse(a: &RgbaImage, b: &RgbaImage) -> Result<f64> {
+    ensure_same_size(a, b)?;
+    let samples = a.as_raw().len();
+    if samples == 0 {
+        return Ok(0.0);
+    }
+    let sum: f64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&x, &y)| {
+            let d = x as f64 - y as f64;
+            d * d
+        })
+        .sum();
+    Ok((sum / samples as f64).sqrt())
+}
+
+pub fn psnr(a: &RgbaImage, b: &RgbaImage) -> Result<f64> {
+    Ok(psnr_from_rmse(rmse(a, b)?))
+}
+
+fn psnr_from_rmse(rmse: f64) -> f64 {
+    if rmse == 0.0 {
+        f64::INFINITY
+    } else {
+        20.0 * (255.0 / rmse).log10()
+    }
+}
+
+pub fn max_channel_error(a: &RgbaImage, b: &RgbaImage) -> Result<u8> {
+    ensure_same_size(a, b)?;
+    Ok(a.as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&x, &y)| x.abs_diff(y))
+        .max()
+        .unwrap_or(0))
+}
+
+fn luma(p: &Rgba<u8>) -> f64 {
+    let [r, g, b, _] = p.0;
+    0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+}
+
+/// Mean structural similarity of the two images' luma, computed over
+/// overlapping square windows. Images smaller than one window are treated as a
+/// single window.
+pub fn ssim(a: &RgbaImage, b: &RgbaImage) -> Result<f64> {
+    ensure_same_size(a, b)?;
+    let (width, height) = a.dimensions();
+    if width == 0 || height == 0 {
+        return Ok(1.0);
+    }
+
+    let window_w = SSIM_WINDOW.min(width);
+    let window_h = SSIM_WINDOW.min(height);
+    let mut total = 0.0;
+    let mut windows = 0usize;
+    let mut y = 0;
+    loop {
+        let mut x = 0;
+        loop {
+            total += window_ssim(a, b, x, y, window_w, window_h);
+            windows += 1;
+            if x + window_w >= width {
+                break;
+            }
+            x = (x + SSIM_STRIDE).min(width - window_w);
+        }
+        if y + window_h >= height {
+            break;
+        }
+        y = (y + SSIM_STRIDE).min(height - window_h);
+    }
+
+    Ok(total / windows as f64)
+}
+
+fn window_ssim(a: &RgbaImage, b: &RgbaImage, x0: u32, y0: u32, w: u32, h: u32) -> f64 {
+    let n = (w * h) as f64;
+    let (mut sum_a, mut sum_b) = (0.0, 0.0);
+    let (mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0);
+    for y in y0..y0 + h {
+        for x in x0..x0 + w {
+            let la = luma(a.get_pixel(x, y));
+            let lb = luma(b.get_pixel(x, y));
+            sum_a += la;
+            sum_b += lb;
+            sum_aa += la * la;
+            sum_bb += lb * lb;
+            sum_ab += la * lb;
+        }
+    }
+    let mean_a = sum_a / n;
+    let mean_b = sum_b / n;
+    let var_a = sum_aa / n - mean_a * mean_a;
+    let var_b = sum_bb / n - mean_b * mean_b;
+    let covar = sum_ab / n - mean_a * mean_b;
+
+    ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covar + SSIM_C2))
+        / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2))
+}
+
+/// Builds a false-color image showing where two images differ. Each pixel is
+/// colored by its largest channel error: black where the images match, ramping
+/// through blue, cyan, green and yellow to red at `scale` or above. Pass a
+/// `scale` of 255 to see errors in absolute terms, or something small to make
+/// subtle differences visible.
+pub fn diff_image(a: &RgbaImage, b: &RgbaImage, scale: u8) -> Result<RgbaImage> {
+    ensure_same_size(a, b)?;
+    let scale = scale.max(1) as f64;
+    Ok(RgbaImage::from_fn(a.width(), a.height(), |x, y| {
+        let pa = a.get_pixel(x, y).0;
+        let pb = b.get_pixel(x, y).0;
+        let error = pa
+            .iter()
+            .zip(pb.iter())
+            .map(|(&ca, &cb)| ca.abs_diff(cb))
+            .max()
+            .unwrap_or(0);
+        if error == 0 {
+            Rgba([0, 0, 0, 255])
+        } else {
+            heat(error as f64 / scale)
+        }
+    }))
+}
+
+/// Maps `t` in 0-1 onto a blue-cyan-green-yellow-red ramp.
+fn heat(t: f64) -> Rgba<u8> {
+    const STOPS: [[f64; 3]; 5] = [
+        [0.0, 0.0, 1.0],
+        [0.0, 1.0, 1.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0],
+    ];
+    let t = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let i = (t.floor() as usize).min(STOPS.len() - 2);
+    let f = t - i as f64;
+    let mut rgb = [0u8; 3];
+    for (c, out) in rgb.iter_mut().enumerate() {
+        let v = STOPS[i][c] + (STOPS[i + 1][c] - STOPS[i][c]) * f;
+        *out = (v * 255.0).round() as u8;
+    }
+    Rgba([rgb[0], rgb[1], rgb[2], 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x * 16) as u8, (y * 16) as u8, ((x + y) * 8) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn identical_images_match_exactly() {
+        let image = gradient(16, 16);
+        let metrics = compare(&image, &image).unwrap();
+        assert_eq!(
+            metrics,
+            Metrics {
+                rmse: 0.0,
+                psnr: f64::INFINITY,
+                ssim: 1.0,
+                max_channel_error: 0,
+            }
+        );
+        assert!(metrics.failures(&Thresholds::EXACT).is_empty());
+    }
+
+    #[test]
+    fn one_pixel_difference() {
+        let a = gradient(4, 4);
+        let mut b = a.clone();
+        b.get_pixel_mut(1, 2)[0] += 40;
+        let metrics = compare(&a, &b).unwrap();
+        // One channel of the 64 is off by 40
+        assert_eq!(metrics.rmse, 5.0);
+        assert_eq!(metrics.psnr, 20.0 * 51f64.log10());
+        assert_eq!(metrics.max_channel_error, 40);
+        assert!(metrics.ssim < 1.0);
+        assert_eq!(metrics.failures(&Thresholds::default()).len(), 4);
+
+        let diff = diff_image(&a, &b, 40).unwrap();
+        for (x, y, pixel) in diff.enumerate_pixels() {
+            let expected = if (x, y) == (1, 2) {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            };
+            assert_eq!(*pixel, expected, "at {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn mismatched_sizes_are_errors() {
+        let mismatch = SizeMismatch {
+            a: (4, 4),
+            b: (4, 3),
+        };
+        assert_eq!(compare(&gradient(4, 4), &gradient(4, 3)), Err(mismatch));
+        assert_eq!(
+            diff_image(&gradient(4, 4), &gradient(4, 3), 1),
+            Err(mismatch)
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Parser, ValueEnum};
+use log::LevelFilter;
+use wgpu::{Backends, CompositeAlphaMode, PowerPreference, PresentMode, TextureFormat};
+
+use crate::capabilities::SurfacePreferences;
+
+/// Settings for a run of the renderer. On native these are parsed from the
+/// command line; the wasm build always uses [`Config::default`].
+#[derive(Clone, Debug, Parser)]
+#[command(version, about)]
+pub struct Config {
+    /// OBJ model to draw instead of the built-in square, relative to the
+    /// resource directory
+    #[arg(long)]
+    pub model: Option<String>,
+    /// Texture to apply to the model, relative to the resource directory
+    #[arg(long, default_value = "blue_square_arrows_up_right.png")]
+    pub texture: String,
+    /// Logical width of the window
+    #[arg(long, default_value_t = 400)]
+    pub window_width: u32,
+    /// Logical height of the window
+    #[arg(long, default_value_t = 400)]
+    pub window_height: u32,
+    /// Width in pixels of the surface rendered into
+    #[arg(long, default_value_t = 800)]
+    pub surface_width: u32,
+    /// Height in pixels of the surface rendered into
+    #[arg(long, default_value_t = 800)]
+    pub surface_height: u32,
+    #[arg(long, value_enum, default_value_t = Backend::All)]
+    pub backend: Backend,
+    #[arg(long, value_enum, default_value_t = Power::HighPerformance)]
+    pub power_preference: Power,
+    /// Name, or part of the name, of the adapter to use instead of the one
+    /// wgpu prefers
+    #[arg(long)]
+    pub adapter: Option<String>,
+    /// Print every adapter the selected backends can find, then exit
+    #[arg(long)]
+    pub list_adapters: bool,
+    /// Present modes to try, in order of preference
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "fifo")]
+    pub present_mode: Vec<PresentModeArg>,
+    /// Composite alpha modes to try, in order of preference
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "opaque,auto")]
+    pub alpha_mode: Vec<AlphaModeArg>,
+    /// Surface formats to try, in order of preference
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "bgra8-unorm-srgb,rgba8-unorm-srgb"
+    )]
+    pub surface_format: Vec<FormatArg>,
+    /// Number of samples per pixel; 1 disables multisampling
+    #[arg(long, default_value_t = 1, value_parser = parse_msaa)]
+    pub msaa: u32,
+    /// Number of fixed simulation steps per second
+    #[arg(long, default_value_t = 60.0, value_parser = parse_rate)]
+    pub update_rate: f64,
+    /// Maximum frames per second; unlimited if not given
+    #[arg(long, value_parser = parse_rate)]
+    pub max_fps: Option<f64>,
+    /// Only redraw in response to input or when the app requests it
+    #[arg(long)]
+    pub on_demand: bool,
+    /// Load the shader from the source tree instead of the copy compiled into
+    /// the binary, and reload it whenever it changes (native only)
+    #[arg(long)]
+    pub watch_shaders: bool,
+    /// Read assets from this directory instead of the ones the build copied
+    /// or embedded (native only)
+    #[arg(long)]
+    pub asset_dir: Option<PathBuf>,
+    /// Read assets from this pack, as written by the build script, instead of
+    /// from loose files. The pack must not change while the engine runs
+    /// (native only)
+    #[arg(long, conflicts_with_all = ["asset_dir", "watch_assets"])]
+    pub asset_pack: Option<PathBuf>,
+    /// Read assets from the source tree, or from --asset-dir, and reload
+    /// textures and meshes whenever they change (native only)
+    #[arg(long)]
+    pub watch_assets: bool,
+    /// Run the app for a single frame without opening a window and write the
+    /// frame to this path
+    #[arg(long)]
+    pub headless: Option<PathBuf>,
+    /// Draw the --headless frame with the reference CPU renderer instead of
+    /// the GPU. Only the model and texture are drawn, so apps that draw
+    /// anything else are refused
+    #[arg(long, requires = "headless")]
+    pub cpu: bool,
+    /// Log level; if not given, RUST_LOG is used
+    #[arg(long)]
+    pub log_level: Option<LevelFilter>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::parse_from([env!("CARGO_PKG_NAME")])
+    }
+}
+
+impl Config {
+    pub fn surface_preferences(&self) -> SurfacePreferences {
+        SurfacePreferences {
+            formats: self.surface_format.iter().map(|&f| f.into()).collect(),
+            present_modes: self.present_mode.iter().map(|&m| m.into()).collect(),
+            alpha_modes: self.alpha_mode.iter().map(|&m| m.into()).collect(),
+        }
+    }
+}
+
+fn parse_msaa(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(n @ (1 | 2 | 4 | 8 | 16)) => Ok(n),
+        _ => Err(format!("{} is not one of 1, 2, 4, 8 or 16", s)),
+    }
+}
+
+/// Parses a rate per second whose period [`FrameClock`](crate::frame::FrameClock)
+/// can step by: finite, positive, and no faster than one per nanosecond.
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|_| format!("{} is not a number", s))?;
+    match Duration::try_from_secs_f64(1.0 / rate) {
+        Ok(period) if rate.is_finite() && rate > 0.0 && !period.is_zero() => Ok(rate),
+        _ => Err(format!(
+            "{} is not a positive rate of at most 1e9 per second",
+            s
+        )),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    All,
+    Primary,
+    Secondary,
+    Vulkan,
+    Metal,
+    Dx12,
+    Gl,
+    BrowserWebgpu,
+}
+
+impl From<Backend> for Backends {
+    fn from(value: Backend) -> Self {
+        match value {
+            Backend::All => Backends::all(),
+            Backend::Primary => Backends::PRIMARY,
+            Backend::Secondary => Backends::SECONDARY,
+            Backend::Vulkan => Backends::VULKAN,
+            Backend::Metal => Backends::METAL,
+            Backend::Dx12 => Backends::DX12,
+            Backend::Gl => Backends::GL,
+            Backend::BrowserWebgpu => Backends::BROWSER_WEBGPU,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Power {
+    None,
+    LowPower,
+    HighPerformance,
+}
+
+impl From<Power> for PowerPreference {
+    fn from(value: Power) -> Self {
+        match value {
+            Power::None => PowerPreference::None,
+            Power::LowPower => PowerPreference::LowPower,
+            Power::HighPerformance => PowerPreference::HighPerformance,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PresentModeArg {
+    AutoVsync,
+    AutoNoVsync,
+    Fifo,
+    FifoRelaxed,
+    Immediate,
+    Mailbox,
+}
+
+impl From<PresentModeArg> for PresentMode {
+    fn from(value: PresentModeArg) -> Self {
+        match value {
+            PresentModeArg::AutoVsync => PresentMode::AutoVsync,
+            PresentModeArg::AutoNoVsync => PresentMode::AutoNoVsync,
+            PresentModeArg::Fifo => PresentMode::Fifo,
+            PresentModeArg::FifoRelaxed => PresentMode::FifoRelaxed,
+            PresentModeArg::Immediate => PresentMode::Immediate,
+            PresentModeArg::Mailbox => PresentMode::Mailbox,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AlphaModeArg {
+    Auto,
+    Opaque,
+    PreMultiplied,
+    PostMultiplied,
+    Inherit,
+}
+
+impl From<AlphaModeArg> for CompositeAlphaMode {
+    fn from(value: AlphaModeArg) -> Self {
+        match value {
+            AlphaModeArg::Auto => CompositeAlphaMode::Auto,
+            AlphaModeArg::Opaque => CompositeAlphaMode::Opaque,
+            AlphaModeArg::PreMultiplied => CompositeAlphaMode::PreMultiplied,
+            AlphaModeArg::PostMultiplied => CompositeAlphaMode::PostMultiplied,
+            AlphaModeArg::Inherit => CompositeAlphaMode::Inherit,
+        }
+    }
+}
+
+/// The surface formats that are commonly offered for presentation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FormatArg {
+    Bgra8UnormSrgb,
+    Rgba8UnormSrgb,
+    Bgra8Unorm,
+    Rgba8Unorm,
+    Rgb10a2Unorm,
+    Rgba16Float,
+}
+
+impl From<FormatArg> for TextureFormat {
+    fn from(value: FormatArg) -> Self {
+        match value {
+            FormatArg::Bgra8UnormSrgb => TextureFormat::Bgra8UnormSrgb,
+            FormatArg::Rgba8UnormSrgb => TextureFormat::Rgba8UnormSrgb,
+            FormatArg::Bgra8Unorm => TextureFormat::Bgra8Unorm,
+            FormatArg::Rgba8Unorm => TextureFormat::Rgba8Unorm,
+            FormatArg::Rgb10a2Unorm => TextureFormat::Rgb10a2Unorm,
+            FormatArg::Rgba16Float => TextureFormat::Rgba16Float,
+        }
+    }
+}
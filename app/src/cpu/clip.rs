@@ -0,0 +1,256 @@
+use super::{
+    math::{Vec3, Vec4},
+    shader::Varying,
+};
+
+/// How far triangles can reach past the sides of the viewport, as a multiple
+/// of its half size, before they're clipped to them. Anything inside is
+/// rasterized whole, and only the pixels in the viewport are visited.
+pub const GUARD_BAND: f32 = 16.0;
+
+/// The planes a corner must be on the positive side of, as the coefficients
+/// of its distance from them in clip space. The sides are pushed out to the
+/// guard band; the near and far planes are wgpu's, where z runs from 0 to w.
+const PLANES: [Vec4; 6] = [
+    Vec4::new(1.0, 0.0, 0.0, GUARD_BAND),
+    Vec4::new(-1.0, 0.0, 0.0, GUARD_BAND),
+    Vec4::new(0.0, 1.0, 0.0, GUARD_BAND),
+    Vec4::new(0.0, -1.0, 0.0, GUARD_BAND),
+    Vec4::new(0.0, 0.0, 1.0, 0.0),
+    Vec4::new(0.0, 0.0, -1.0, 1.0),
+];
+
+/// Clips a triangle in clip space to the near and far planes, and to the
+/// guard band at the sides, returning a fan of triangles covering what's
+/// left in the same winding order.
+///
+/// Like the GPU's clipper, this works on homogeneous coordinates, before
+/// the divide by w, so triangles that cross behind the viewer are cut at the
+/// near plane rather than wrapping around. New corners interpolate the
+/// varyings linearly in clip space, which keeps them perspective-correct.
+pub fn clip<V: Varying>(triangle: [(Vec4, V); 3]) -> impl Iterator<Item = [(Vec4, V); 3]> {
+    let distances = triangle.map(|(position, _)| PLANES.map(|plane| plane.dot(position)));
+    let mut inside = true;
+    for plane in 0..PLANES.len() {
+        let outside = distances.iter().filter(|d| d[plane] < 0.0).count();
+        if outside == 3 {
+            return None.into_iter().chain(fan(Vec::new()));
+        }
+        inside &= outside == 0;
+    }
+    if inside {
+        return Some(triangle).into_iter().chain(fan(Vec::new()));
+    }
+
+    let mut polygon = triangle.to_vec();
+    let mut next = Vec::with_capacity(triangle.len() + PLANES.len());
+    for plane in PLANES {
+        if polygon
+            .iter()
+            .all(|(position, _)| plane.dot(*position) >= 0.0)
+        {
+            continue;
+        }
+        // Sutherland–Hodgman: keep the corners inside the plane, and add one
+        // wherever an edge crosses it
+        next.clear();
+        for (i, &(a, a_varyings)) in polygon.iter().enumerate() {
+            let (b, b_varyings) = polygon[(i + 1) % polygon.len()];
+            let (da, db) = (plane.dot(a), plane.dot(b));
+            if da >= 0.0 {
+                next.push((a, a_varyings));
+            }
+            if (da >= 0.0) != (db >= 0.0) {
+                let t = da / (da - db);
+                let weights = Vec3::new(1.0 - t, t, 0.0);
+                next.push((
+                    a.lerp(b, t),
+                    V::interpolate([a_varyings, b_varyings, b_varyings], weights),
+                ));
+            }
+        }
+        std::mem::swap(&mut polygon, &mut next);
+        if polygon.len() < 3 {
+            break;
+        }
+    }
+    None.into_iter().chain(fan(polygon))
+}
+
+/// Splits a convex polygon into triangles sharing its first corner.
+fn fan<V: Varying>(polygon: Vec<(Vec4, V)>) -> impl Iterator<Item = [(Vec4, V); 3]> {
+    (2..polygon.len()).map(move |i| [polygon[0], polygon[i - 1], polygon[i]])
+}
+
+/// Clips a line in clip space against the same planes as [`clip`], returning
+/// `None` if none of it is left.
+pub fn clip_line(from: Vec4, to: Vec4) -> Option<(Vec4, Vec4)> {
+    let (mut start, mut end) = (0.0f32, 1.0f32);
+    for plane in PLANES {
+        let (da, db) = (plane.dot(from), plane.dot(to));
+        if da < 0.0 && db < 0.0 {
+            return None;
+        }
+        if da < 0.0 {
+            start = start.max(da / (da - db));
+        } else if db < 0.0 {
+            end = end.min(da / (da - db));
+        }
+    }
+    (start <= end).then(|| (from.lerp(to, start), from.lerp(to, end)))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use wgpu::TextureFormat;
+
+    use super::*;
+    use crate::{
+        bake::TextureData,
+        compare::{compare, Thresholds},
+        config::Config,
+        cpu::{
+            math::Mat4,
+            raster::{draw, Framebuffer},
+            shaders::Textured,
+            texture::Texture,
+        },
+        engine::{Engine, Mesh, ModelVertex, CLEAR_COLOR},
+        error::EngineError,
+    };
+
+    fn corner(x: f32, y: f32, z: f32, value: f32) -> (Vec4, f32) {
+        (Vec4::new(x, y, z, 1.0), value)
+    }
+
+    #[test]
+    fn crossing_the_near_plane_adds_corners_where_edges_cross_it() {
+        let triangle = [
+            corner(0.0, 0.0, -1.0, 0.0),
+            corner(1.0, 0.0, 1.0, 1.0),
+            corner(0.0, 1.0, 1.0, 2.0),
+        ];
+        let clipped: Vec<_> = clip(triangle).collect();
+        // The corner behind the near plane is replaced by the midpoints of
+        // its edges, with the values there, and the quad left is split into
+        // two triangles in the same winding order
+        let ab = corner(0.5, 0.0, 0.0, 0.5);
+        let ca = corner(0.0, 0.5, 0.0, 1.0);
+        assert_eq!(
+            clipped,
+            vec![[ab, triangle[1], triangle[2]], [ab, triangle[2], ca]]
+        );
+    }
+
+    #[test]
+    fn triangles_outside_a_plane_are_culled() {
+        let beside = [
+            corner(17.0, 0.0, 0.5, 0.0),
+            corner(20.0, 5.0, 0.5, 0.0),
+            corner(18.0, -5.0, 0.5, 0.0),
+        ];
+        assert_eq!(clip(beside).count(), 0);
+        let behind = [
+            corner(0.0, 0.0, 1.5, 0.0),
+            corner(1.0, 0.0, 1.1, 0.0),
+            corner(0.0, 1.0, 2.0, 0.0),
+        ];
+        assert_eq!(clip(behind).count(), 0);
+    }
+
+    #[test]
+    fn triangles_inside_the_guard_band_are_not_clipped() {
+        let triangle = [
+            corner(-15.0, -10.0, 0.2, 0.0),
+            corner(15.0, -12.0, 0.5, 1.0),
+            corner(3.0, 14.0, 0.9, 2.0),
+        ];
+        assert_eq!(clip(triangle).collect::<Vec<_>>(), vec![triangle]);
+    }
+
+    /// Triangles that cross the near and far planes, the sides of the
+    /// viewport and the guard band, each colored by one texel of `PALETTE`
+    /// so that only coverage can differ.
+    fn clip_test_mesh() -> Mesh {
+        let triangles = [
+            [[-0.9, -0.8, -0.5], [0.7, -0.6, 0.8], [-0.2, 0.9, 0.3]],
+            [[-60.0, -0.9, 0.5], [40.0, -0.3, 0.2], [0.1, 70.0, 0.6]],
+            [[0.2, -0.9, 1.7], [0.95, 0.1, 0.4], [0.5, 0.8, -0.3]],
+            [[-3.0, -2.0, -0.8], [2.5, -1.5, 1.9], [0.0, 3.0, 0.5]],
+            [[-0.5, -0.5, 0.1], [9.0, -0.4, 0.1], [-0.3, 0.6, 0.1]],
+        ];
+        let mut mesh = Mesh::default();
+        for (i, triangle) in triangles.iter().enumerate() {
+            let u = (i as f32 + 0.5) / triangles.len() as f32;
+            for &position in triangle {
+                mesh.indices.push(mesh.vertices.len() as u32);
+                mesh.vertices.push(ModelVertex {
+                    position,
+                    tex_coords: [u, 0.5],
+                });
+            }
+        }
+        mesh
+    }
+
+    /// Renders the clip test mesh on the GPU and the CPU through a camera
+    /// that gives the corners different values of w, and checks that the
+    /// images match.
+    #[test]
+    fn clipping_matches_the_gpu() {
+        let config = Config {
+            surface_width: 128,
+            surface_height: 128,
+            ..Config::default()
+        };
+        let palette = RgbaImage::from_fn(5, 1, |x, _| {
+            [
+                Rgba([255, 0, 0, 255]),
+                Rgba([0, 255, 0, 255]),
+                Rgba([0, 0, 255, 255]),
+                Rgba([255, 255, 0, 255]),
+                Rgba([255, 0, 255, 255]),
+            ][x as usize]
+        });
+        let mut texture = TextureData::from_image(&palette);
+        texture.levels.truncate(1);
+        let mesh = clip_test_mesh();
+        // w = 1 + z / 2, so clipping happens in homogeneous space
+        let mut camera = Mat4::IDENTITY;
+        camera.cols[2].w = 0.5;
+
+        let gpu = pollster::block_on(async {
+            let mut engine = Engine::new_headless(&config).await?;
+            engine.set_mesh(mesh.clone());
+            engine.set_texture(texture.clone(), "palette").await?;
+            engine.set_camera(camera);
+            engine.render_to_image().await
+        });
+        let gpu = match gpu {
+            Err(EngineError::NoAdapter(e)) => {
+                eprintln!("Skipping the GPU comparison: {}", e);
+                return;
+            }
+            result => result.expect("GPU render failed"),
+        };
+
+        let shader = Textured {
+            texture: Texture::from_texture_data(&texture, TextureFormat::Rgba8UnormSrgb),
+            camera,
+        };
+        let mut target = Framebuffer::new(config.surface_width, config.surface_height);
+        let clear = CLEAR_COLOR;
+        target.clear(Vec4::new(
+            clear.r as f32,
+            clear.g as f32,
+            clear.b as f32,
+            clear.a as f32,
+        ));
+        draw(&mut target, &shader, &mesh.vertices, &mesh.indices);
+
+        let metrics = compare(&gpu, &target.color).unwrap();
+        let failures = metrics.failures(&Thresholds::default());
+        assert!(failures.is_empty(), "{}: {}", metrics, failures.join(", "));
+    }
+}
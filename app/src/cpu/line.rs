@@ -0,0 +1,342 @@
+use image::{Rgba, RgbaImage};
+
+use super::math::Vec2;
+use crate::bake::{linear_to_srgb, srgb_to_linear};
+
+/// How the ends of a thick line are drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cap {
+    /// Stops square at the end points.
+    Butt,
+    /// Extends past the end points by half the width.
+    Square,
+    /// Ends in a half circle around each end point.
+    Round,
+}
+
+/// Which of the line algorithms to draw with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stroke {
+    /// One pixel wide and hard edged, with [`bresenham`].
+    Aliased,
+    /// One pixel wide and smoothed, with [`wu`].
+    AntiAliased,
+    /// Any width, smoothed, with [`thick`].
+    Thick { width: f32, cap: Cap },
+}
+
+/// Draws a line from `from` to `to` in framebuffer coordinates, where pixel
+/// centers are at half coordinates.
+pub fn line(image: &mut RgbaImage, from: Vec2, to: Vec2, color: Rgba<u8>, stroke: Stroke) {
+    match stroke {
+        Stroke::Aliased => {
+            let pixel = |p: Vec2| (p.x.floor() as i64, p.y.floor() as i64);
+            bresenham(image, pixel(from), pixel(to), color);
+        }
+        Stroke::AntiAliased => wu(image, from, to, color),
+        Stroke::Thick { width, cap } => thick(image, from, to, width, cap, color),
+    }
+}
+
+/// Bresenham's algorithm, as in the first lesson of tinyrenderer: sets every
+/// pixel from `from` to `to` inclusive to `color`, stepping one pixel at a
+/// time along the longer axis using only integer arithmetic. The segment is
+/// clipped to the image first, so only the steps that land on it are taken.
+pub fn bresenham(image: &mut RgbaImage, from: (i64, i64), to: (i64, i64), color: Rgba<u8>) {
+    let (width, height) = (i128::from(image.width()), i128::from(image.height()));
+    let (x0, y0) = (i128::from(from.0), i128::from(from.1));
+    let (x1, y1) = (i128::from(to.0), i128::from(to.1));
+    // Step along the longer axis, u, and sometimes along the shorter, v
+    let steep = (y1 - y0).abs() > (x1 - x0).abs();
+    let ((u0, u1, u_size), (v0, v1, v_size)) = if steep {
+        ((y0, y1, height), (x0, x1, width))
+    } else {
+        ((x0, x1, width), (y0, y1, height))
+    };
+    let (du, dv) = ((u1 - u0).unsigned_abs(), (v1 - v0).unsigned_abs());
+    let (step_u, step_v) = ((u1 - u0).signum(), (v1 - v0).signum());
+    // After `i` steps along u, the line has taken round(i * dv / du) steps
+    // along v, rounding halves up. `error` is how far the line is past the
+    // near edge of that pixel, scaled by 2 * du so it stays an integer
+    let scale = du.max(1);
+    let state = |i: u128| {
+        let (steps, rest) = (dv * i / scale, dv * i % scale);
+        let error = 2 * rest + scale;
+        (steps + error / (2 * scale), error % (2 * scale))
+    };
+    // How far along v step `i` is from the edge of the image the line
+    // enters from
+    let progress = |i: u128| {
+        let v = v0 + step_v * state(i).0 as i128;
+        if step_v < 0 {
+            v_size - 1 - v
+        } else {
+            v
+        }
+    };
+
+    // The steps whose u is on the image, then of those, the ones whose v is.
+    // v only ever moves one way, so they're found by bisection
+    let (first, last) = if step_u < 0 {
+        (u0 - (u_size - 1), u0)
+    } else {
+        (-u0, u_size - 1 - u0)
+    };
+    let (first, last) = (first.max(0) as u128, last.min(du as i128));
+    if last < first as i128 {
+        return;
+    }
+    let first = partition_point(first, last as u128 + 1, |i| progress(i) < 0);
+    let end = partition_point(first, last as u128 + 1, |i| progress(i) < v_size);
+
+    let (mut steps, mut error) = state(first);
+    for i in first..end {
+        let u = u0 + step_u * i as i128;
+        let v = v0 + step_v * steps as i128;
+        let (x, y) = if steep { (v, u) } else { (u, v) };
+        image.put_pixel(x as u32, y as u32, color);
+        error += 2 * dv;
+        if error >= 2 * scale {
+            error -= 2 * scale;
+            steps += 1;
+        }
+    }
+}
+
+/// The first index in `start..end` for which `pred` is false, where `pred`
+/// is true for some prefix of the range and false for the rest.
+fn partition_point(mut start: u128, mut end: u128, pred: impl Fn(u128) -> bool) -> u128 {
+    while start < end {
+        let mid = start + (end - start) / 2;
+        if pred(mid) {
+            start = mid + 1;
+        } else {
+            end = mid;
+        }
+    }
+    start
+}
+
+/// Xiaolin Wu's algorithm: draws a one pixel wide anti-aliased line by
+/// splitting each step along the longer axis between the two pixels the line
+/// passes between, weighted by how close it is to each. The end points are
+/// weighted by how much of their pixel the line covers.
+pub fn wu(image: &mut RgbaImage, from: Vec2, to: Vec2, color: Rgba<u8>) {
+    // The algorithm puts pixel centers on whole coordinates
+    let (mut from, mut to) = (from - Vec2::splat(0.5), to - Vec2::splat(0.5));
+    let steep = (to.y - from.y).abs() > (to.x - from.x).abs();
+    if steep {
+        from = Vec2::new(from.y, from.x);
+        to = Vec2::new(to.y, to.x);
+    }
+    if from.x > to.x {
+        std::mem::swap(&mut from, &mut to);
+    }
+    let delta = to - from;
+    let gradient = if delta.x == 0.0 {
+        1.0
+    } else {
+        delta.y / delta.x
+    };
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let mut plot = |x: f32, y: f32, coverage: f32| {
+        let (x, y) = if steep { (y, x) } else { (x, y) };
+        blend(image, x as i64, y as i64, color, coverage);
+    };
+    let fract = |v: f32| v - v.floor();
+    let mut end = |p: Vec2, gap: f32| {
+        let x = p.x.round();
+        let y = p.y + gradient * (x - p.x);
+        plot(x, y.floor(), (1.0 - fract(y)) * gap);
+        plot(x, y.floor() + 1.0, fract(y) * gap);
+        x
+    };
+    let first = end(from, 1.0 - fract(from.x + 0.5));
+    let last = end(to, fract(to.x + 0.5));
+
+    // Only step through the columns that are on the image
+    let columns = if steep { height } else { width };
+    let start = (first + 1.0).max(0.0);
+    let stop = last.min(columns as f32);
+    let mut y = from.y + gradient * (start - from.x);
+    let mut x = start;
+    while x < stop {
+        plot(x, y.floor(), 1.0 - fract(y));
+        plot(x, y.floor() + 1.0, fract(y));
+        y += gradient;
+        x += 1.0;
+    }
+}
+
+/// Draws an anti-aliased line `width` pixels wide, with the given caps, by
+/// covering each pixel near it by how far its center is inside the line's
+/// outline.
+pub fn thick(image: &mut RgbaImage, from: Vec2, to: Vec2, width: f32, cap: Cap, color: Rgba<u8>) {
+    let half = width * 0.5;
+    let delta = to - from;
+    let length = delta.length();
+    let direction = if length > 0.0 {
+        delta / length
+    } else {
+        Vec2::new(1.0, 0.0)
+    };
+    let extend = match cap {
+        Cap::Butt => 0.0,
+        Cap::Square | Cap::Round => half,
+    };
+
+    let margin = Vec2::splat(half + 1.0);
+    let min = (from.min(to) - margin).max(Vec2::ZERO);
+    let max = (from.max(to) + margin).min(Vec2::new(image.width() as f32, image.height() as f32));
+    for y in min.y as u32..max.y.ceil() as u32 {
+        for x in min.x as u32..max.x.ceil() as u32 {
+            let offset = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - from;
+            // The signed distance from the outline, negative inside
+            let distance = match cap {
+                Cap::Round => {
+                    let t = offset.dot(direction).clamp(0.0, length);
+                    (offset - direction * t).length() - half
+                }
+                Cap::Butt | Cap::Square => {
+                    let along =
+                        (offset.dot(direction) - length * 0.5).abs() - (length * 0.5 + extend);
+                    let across = (offset.x * direction.y - offset.y * direction.x).abs() - half;
+                    let outside = Vec2::new(along.max(0.0), across.max(0.0)).length();
+                    outside + along.max(across).min(0.0)
+                }
+            };
+            // Pixels within half a pixel of the outline are partly covered
+            let coverage = (0.5 - distance).clamp(0.0, 1.0);
+            if coverage > 0.0 {
+                blend(image, x.into(), y.into(), color, coverage);
+            }
+        }
+    }
+}
+
+fn pixel_mut(image: &mut RgbaImage, x: i64, y: i64) -> Option<&mut Rgba<u8>> {
+    let (x, y) = (u32::try_from(x).ok()?, u32::try_from(y).ok()?);
+    (x < image.width() && y < image.height()).then(|| image.get_pixel_mut(x, y))
+}
+
+/// Draws `color` over the pixel at `x`, `y` with its alpha scaled by
+/// `coverage`, mixing in linear space as a blending sRGB target does.
+fn blend(image: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>, coverage: f32) {
+    let Some(pixel) = pixel_mut(image, x, y) else {
+        return;
+    };
+    let alpha = f32::from(color[3]) / 255.0 * coverage.clamp(0.0, 1.0);
+    for i in 0..3 {
+        let dst = srgb_to_linear(pixel[i]);
+        let src = srgb_to_linear(color[i]);
+        pixel[i] = linear_to_srgb(dst + (src - dst) * alpha);
+    }
+    let dst = f32::from(pixel[3]) / 255.0;
+    pixel[3] = ((alpha + dst * (1.0 - alpha)) * 255.0).round() as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Rgba<u8> = Rgba([255; 4]);
+
+    fn drawn(image: &RgbaImage) -> usize {
+        image.pixels().filter(|pixel| pixel[3] > 0).count()
+    }
+
+    #[test]
+    fn bresenham_sets_one_pixel_per_step_in_every_octant() {
+        let center = (16, 16);
+        for dx in -12i64..=12 {
+            for dy in -12i64..=12 {
+                let to = (center.0 + dx, center.1 + dy);
+                let mut image = RgbaImage::new(32, 32);
+                bresenham(&mut image, center, to, WHITE);
+                let steps = dx.abs().max(dy.abs()) as usize;
+                assert_eq!(drawn(&image), steps + 1, "to {:?}", to);
+                for (x, y) in [center, to] {
+                    assert_eq!(*image.get_pixel(x as u32, y as u32), WHITE, "to {:?}", to);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bresenham_clips_to_the_image() {
+        // The same line on an image big enough to hold all of it
+        let (from, to) = ((-20, -7), (35, 30));
+        let mut whole = RgbaImage::new(80, 80);
+        bresenham(
+            &mut whole,
+            (from.0 + 30, from.1 + 30),
+            (to.0 + 30, to.1 + 30),
+            WHITE,
+        );
+        let mut clipped = RgbaImage::new(16, 16);
+        bresenham(&mut clipped, from, to, WHITE);
+        assert!(clipped == image::imageops::crop_imm(&whole, 30, 30, 16, 16).to_image());
+
+        // Only the visible steps are taken, so this doesn't take forever
+        let mut image = RgbaImage::new(16, 16);
+        bresenham(&mut image, (-1 << 50, 0), (1 << 50, 5), WHITE);
+        assert_eq!(drawn(&image), 16);
+    }
+
+    #[test]
+    fn wu_covers_each_column_once() {
+        for (from, to) in [
+            (Vec2::new(2.5, 3.2), Vec2::new(29.5, 11.7)),
+            (Vec2::new(30.1, 2.0), Vec2::new(1.3, 9.9)),
+            // Steep lines are stepped by row
+            (Vec2::new(4.2, 1.5), Vec2::new(12.9, 30.5)),
+        ] {
+            let mut image = RgbaImage::new(32, 32);
+            wu(&mut image, from, to, WHITE);
+            let steep = (to.y - from.y).abs() > (to.x - from.x).abs();
+            let (start, end) = if steep {
+                (from.y, to.y)
+            } else {
+                (from.x, to.x)
+            };
+            // The end columns are only partly covered
+            let columns = start.min(end).ceil() as u32 + 1..end.max(start).floor() as u32 - 1;
+            for column in columns {
+                let coverage: f32 = (0..32)
+                    .map(|i| {
+                        let (x, y) = if steep { (i, column) } else { (column, i) };
+                        f32::from(image.get_pixel(x, y)[3]) / 255.0
+                    })
+                    .sum();
+                assert!(
+                    (coverage - 1.0).abs() < 0.01,
+                    "{} in column {} of {:?} to {:?}",
+                    coverage,
+                    column,
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn caps_extend_as_described() {
+        let (from, to) = (Vec2::new(8.0, 16.0), Vec2::new(24.0, 16.0));
+        for (cap, extent, corner) in [
+            (Cap::Butt, 8..24, None),
+            (Cap::Square, 6..26, Some(255)),
+            (Cap::Round, 6..26, Some(97)),
+        ] {
+            let mut image = RgbaImage::new(32, 32);
+            let stroke = Stroke::Thick { width: 4.0, cap };
+            line(&mut image, from, to, WHITE, stroke);
+            let row: Vec<_> = (0..32).filter(|&x| image.get_pixel(x, 15)[3] > 0).collect();
+            assert_eq!(row, extent.collect::<Vec<_>>(), "{:?}", cap);
+            // The pixel past the end point and beside the line's center
+            if let Some(alpha) = corner {
+                assert_eq!(image.get_pixel(6, 14)[3], alpha, "{:?}", cap);
+            }
+        }
+    }
+}
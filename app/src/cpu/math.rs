@@ -0,0 +1,343 @@
+use std::ops::{Add, AddAssign, Div, Index, Mul, MulAssign, Neg, Sub, SubAssign};
+
+macro_rules! vector {
+    ($name:ident, $n:literal, $($field:ident),+) => {
+        #[derive(Clone, Copy, Debug, Default, PartialEq)]
+        pub struct $name {
+            $(pub $field: f32),+
+        }
+
+        impl $name {
+            pub const ZERO: Self = Self::splat(0.0);
+            pub const ONE: Self = Self::splat(1.0);
+
+            pub const fn new($($field: f32),+) -> Self {
+                Self { $($field),+ }
+            }
+
+            pub const fn splat(value: f32) -> Self {
+                Self { $($field: value),+ }
+            }
+
+            pub fn dot(self, other: Self) -> f32 {
+                0.0 $(+ self.$field * other.$field)+
+            }
+
+            pub fn length(self) -> f32 {
+                self.dot(self).sqrt()
+            }
+
+            /// The vector scaled to a length of 1, or zero if it has no length.
+            pub fn normalize(self) -> Self {
+                let length = self.length();
+                if length > 0.0 {
+                    self / length
+                } else {
+                    Self::ZERO
+                }
+            }
+
+            pub fn lerp(self, other: Self, t: f32) -> Self {
+                self + (other - self) * t
+            }
+
+            /// Applies `f` to each component.
+            pub fn map(self, mut f: impl FnMut(f32) -> f32) -> Self {
+                Self { $($field: f(self.$field)),+ }
+            }
+
+            pub fn min(self, other: Self) -> Self {
+                Self { $($field: self.$field.min(other.$field)),+ }
+            }
+
+            pub fn max(self, other: Self) -> Self {
+                Self { $($field: self.$field.max(other.$field)),+ }
+            }
+
+            pub fn to_array(self) -> [f32; $n] {
+                [$(self.$field),+]
+            }
+        }
+
+        impl From<[f32; $n]> for $name {
+            fn from([$($field),+]: [f32; $n]) -> Self {
+                Self { $($field),+ }
+            }
+        }
+
+        impl From<$name> for [f32; $n] {
+            fn from(value: $name) -> Self {
+                value.to_array()
+            }
+        }
+
+        impl Index<usize> for $name {
+            type Output = f32;
+
+            fn index(&self, index: usize) -> &f32 {
+                [$(&self.$field),+][index]
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+
+            fn add(self, other: Self) -> Self {
+                Self { $($field: self.$field + other.$field),+ }
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+
+            fn sub(self, other: Self) -> Self {
+                Self { $($field: self.$field - other.$field),+ }
+            }
+        }
+
+        /// Multiplies component by component.
+        impl Mul for $name {
+            type Output = Self;
+
+            fn mul(self, other: Self) -> Self {
+                Self { $($field: self.$field * other.$field),+ }
+            }
+        }
+
+        impl Mul<f32> for $name {
+            type Output = Self;
+
+            fn mul(self, scale: f32) -> Self {
+                Self { $($field: self.$field * scale),+ }
+            }
+        }
+
+        impl Mul<$name> for f32 {
+            type Output = $name;
+
+            fn mul(self, vector: $name) -> $name {
+                vector * self
+            }
+        }
+
+        impl Div<f32> for $name {
+            type Output = Self;
+
+            fn div(self, scale: f32) -> Self {
+                Self { $($field: self.$field / scale),+ }
+            }
+        }
+
+        impl Neg for $name {
+            type Output = Self;
+
+            fn neg(self) -> Self {
+                Self { $($field: -self.$field),+ }
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, other: Self) {
+                *self = *self + other;
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, other: Self) {
+                *self = *self - other;
+            }
+        }
+
+        impl MulAssign<f32> for $name {
+            fn mul_assign(&mut self, scale: f32) {
+                *self = *self * scale;
+            }
+        }
+    };
+}
+
+vector!(Vec2, 2, x, y);
+vector!(Vec3, 3, x, y, z);
+vector!(Vec4, 4, x, y, z, w);
+
+impl Vec2 {
+    pub fn extend(self, z: f32) -> Vec3 {
+        Vec3::new(self.x, self.y, z)
+    }
+}
+
+impl Vec3 {
+    pub fn cross(self, other: Self) -> Self {
+        Self::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn extend(self, w: f32) -> Vec4 {
+        Vec4::new(self.x, self.y, self.z, w)
+    }
+
+    pub fn xy(self) -> Vec2 {
+        Vec2::new(self.x, self.y)
+    }
+}
+
+impl Vec4 {
+    pub fn xyz(self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+
+    pub fn xy(self) -> Vec2 {
+        Vec2::new(self.x, self.y)
+    }
+}
+
+/// A column-major 4x4 matrix, laid out the way WGSL's `mat4x4<f32>` is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub cols: [Vec4; 4],
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Mat4 {
+    pub const IDENTITY: Self = Self::from_cols(
+        Vec4::new(1.0, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, 1.0, 0.0, 0.0),
+        Vec4::new(0.0, 0.0, 1.0, 0.0),
+        Vec4::new(0.0, 0.0, 0.0, 1.0),
+    );
+
+    pub const fn from_cols(x: Vec4, y: Vec4, z: Vec4, w: Vec4) -> Self {
+        Self { cols: [x, y, z, w] }
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        let mut m = Self::IDENTITY;
+        m.cols[3] = offset.extend(1.0);
+        m
+    }
+
+    pub fn scale(scale: Vec3) -> Self {
+        Self::from_cols(
+            Vec4::new(scale.x, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, scale.y, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, scale.z, 0.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    /// A right-handed view matrix looking from `eye` towards `target`.
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        let forward = (target - eye).normalize();
+        let right = forward.cross(up).normalize();
+        let up = right.cross(forward);
+        Self::from_cols(
+            Vec4::new(right.x, up.x, -forward.x, 0.0),
+            Vec4::new(right.y, up.y, -forward.y, 0.0),
+            Vec4::new(right.z, up.z, -forward.z, 0.0),
+            Vec4::new(-right.dot(eye), -up.dot(eye), forward.dot(eye), 1.0),
+        )
+    }
+
+    /// A right-handed perspective projection into wgpu's clip space, where
+    /// depth runs from 0 at `near` to 1 at `far`. `fov_y` is in radians.
+    pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let f = 1.0 / (fov_y / 2.0).tan();
+        let range = far / (near - far);
+        Self::from_cols(
+            Vec4::new(f / aspect, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, f, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, range, -1.0),
+            Vec4::new(0.0, 0.0, range * near, 0.0),
+        )
+    }
+
+    pub fn row(&self, i: usize) -> Vec4 {
+        Vec4::new(
+            self.cols[0][i],
+            self.cols[1][i],
+            self.cols[2][i],
+            self.cols[3][i],
+        )
+    }
+
+    pub fn transpose(&self) -> Self {
+        Self::from_cols(self.row(0), self.row(1), self.row(2), self.row(3))
+    }
+
+    /// The inverse, or `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let m: [[f32; 4]; 4] = self.cols.map(Vec4::to_array);
+        let at = |c: usize, r: usize| m[c][r];
+        // Cofactors of the 2x2 minors, as in the Laplace expansion
+        let s0 = at(0, 0) * at(1, 1) - at(1, 0) * at(0, 1);
+        let s1 = at(0, 0) * at(1, 2) - at(1, 0) * at(0, 2);
+        let s2 = at(0, 0) * at(1, 3) - at(1, 0) * at(0, 3);
+        let s3 = at(0, 1) * at(1, 2) - at(1, 1) * at(0, 2);
+        let s4 = at(0, 1) * at(1, 3) - at(1, 1) * at(0, 3);
+        let s5 = at(0, 2) * at(1, 3) - at(1, 2) * at(0, 3);
+        let c5 = at(2, 2) * at(3, 3) - at(3, 2) * at(2, 3);
+        let c4 = at(2, 1) * at(3, 3) - at(3, 1) * at(2, 3);
+        let c3 = at(2, 1) * at(3, 2) - at(3, 1) * at(2, 2);
+        let c2 = at(2, 0) * at(3, 3) - at(3, 0) * at(2, 3);
+        let c1 = at(2, 0) * at(3, 2) - at(3, 0) * at(2, 2);
+        let c0 = at(2, 0) * at(3, 1) - at(3, 0) * at(2, 1);
+        let det = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
+        if det == 0.0 {
+            return None;
+        }
+        let inv = 1.0 / det;
+        let col = |a: [f32; 4]| Vec4::from(a) * inv;
+        Some(Self::from_cols(
+            col([
+                at(1, 1) * c5 - at(1, 2) * c4 + at(1, 3) * c3,
+                -at(0, 1) * c5 + at(0, 2) * c4 - at(0, 3) * c3,
+                at(3, 1) * s5 - at(3, 2) * s4 + at(3, 3) * s3,
+                -at(2, 1) * s5 + at(2, 2) * s4 - at(2, 3) * s3,
+            ]),
+            col([
+                -at(1, 0) * c5 + at(1, 2) * c2 - at(1, 3) * c1,
+                at(0, 0) * c5 - at(0, 2) * c2 + at(0, 3) * c1,
+                -at(3, 0) * s5 + at(3, 2) * s2 - at(3, 3) * s1,
+                at(2, 0) * s5 - at(2, 2) * s2 + at(2, 3) * s1,
+            ]),
+            col([
+                at(1, 0) * c4 - at(1, 1) * c2 + at(1, 3) * c0,
+                -at(0, 0) * c4 + at(0, 1) * c2 - at(0, 3) * c0,
+                at(3, 0) * s4 - at(3, 1) * s2 + at(3, 3) * s0,
+                -at(2, 0) * s4 + at(2, 1) * s2 - at(2, 3) * s0,
+            ]),
+            col([
+                -at(1, 0) * c3 + at(1, 1) * c1 - at(1, 2) * c0,
+                at(0, 0) * c3 - at(0, 1) * c1 + at(0, 2) * c0,
+                -at(3, 0) * s3 + at(3, 1) * s1 - at(3, 2) * s0,
+                at(2, 0) * s3 - at(2, 1) * s1 + at(2, 2) * s0,
+            ]),
+        ))
+    }
+}
+
+impl Mul<Vec4> for Mat4 {
+    type Output = Vec4;
+
+    fn mul(self, v: Vec4) -> Vec4 {
+        self.cols[0] * v.x + self.cols[1] * v.y + self.cols[2] * v.z + self.cols[3] * v.w
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self {
+            cols: other.cols.map(|col| self * col),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! A software renderer that runs shaders written in Rust, for machines
+//! without a GPU and for checking the GPU's output against, with or without
+//! multisampling, along with the line drawing and wireframes tinyrenderer
+//! starts with.
+
+pub mod clip;
+pub mod line;
+pub mod math;
+pub mod msaa;
+pub mod raster;
+pub mod renderer;
+pub mod shader;
+pub mod shaders;
+pub mod texture;
+pub mod tiled;
+pub mod wireframe;
+
+pub use line::{line, Cap, Stroke};
+pub use math::{Mat4, Vec2, Vec3, Vec4};
+pub use msaa::{draw_multisampled, MultisampleFramebuffer, SamplePattern, Shading};
+pub use raster::{draw, Framebuffer};
+pub use renderer::CpuRenderer;
+pub use shader::{Fragment, Quad, Shader, Varying};
+pub use texture::{Sampler, Texture};
+pub use tiled::draw_tiled;
+pub use wireframe::draw_wireframe;
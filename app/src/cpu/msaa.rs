@@ -0,0 +1,370 @@
+use image::{Luma, Rgba, RgbaImage};
+
+use super::{
+    clip::clip,
+    math::{Vec2, Vec4},
+    raster::{encode, Framebuffer, Triangle, SUBPIXEL_BITS},
+    shader::Shader,
+};
+use crate::bake::{linear_to_srgb, srgb_to_linear};
+
+/// The most samples a pixel can have, so its coverage fits in a `u32` mask
+/// as it does on the GPU.
+pub const MAX_SAMPLES: usize = 32;
+
+/// The positions WebGPU, Vulkan and Direct3D put samples at, in 1/16ths of a
+/// pixel from its top left corner, for each sample count the engine's
+/// `--msaa` accepts.
+const STANDARD_1: [[i64; 2]; 1] = [[8, 8]];
+const STANDARD_2: [[i64; 2]; 2] = [[12, 12], [4, 4]];
+const STANDARD_4: [[i64; 2]; 4] = [[6, 2], [14, 6], [2, 10], [10, 14]];
+const STANDARD_8: [[i64; 2]; 8] = [
+    [9, 5],
+    [7, 11],
+    [13, 9],
+    [5, 3],
+    [3, 13],
+    [1, 7],
+    [11, 15],
+    [15, 1],
+];
+const STANDARD_16: [[i64; 2]; 16] = [
+    [9, 9],
+    [7, 5],
+    [5, 10],
+    [12, 7],
+    [3, 6],
+    [10, 13],
+    [13, 11],
+    [11, 3],
+    [6, 14],
+    [8, 1],
+    [4, 2],
+    [2, 12],
+    [0, 8],
+    [15, 4],
+    [14, 15],
+    [1, 0],
+];
+
+/// Where in each pixel a [`MultisampleFramebuffer`] takes its samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplePattern {
+    /// The GPU's standard positions for 1, 2, 4, 8 or 16 samples, so the
+    /// coverage matches the engine's at the same `--msaa`. 4 samples are a
+    /// rotated grid, which resolves near-vertical and near-horizontal edges
+    /// better than an ordered one.
+    Standard(u32),
+    /// An n by n grid of evenly spaced samples, for n up to 5.
+    OrderedGrid(u32),
+}
+
+impl SamplePattern {
+    pub fn count(&self) -> usize {
+        match *self {
+            Self::Standard(count) => count as usize,
+            Self::OrderedGrid(n) => (n * n) as usize,
+        }
+    }
+
+    /// The fixed-point offsets of the samples from the top left corner of
+    /// their pixel, in the order their bits are in coverage masks.
+    ///
+    /// # Panics
+    ///
+    /// If a standard pattern isn't for 1, 2, 4, 8 or 16 samples, or a grid
+    /// has none or more than [`MAX_SAMPLES`].
+    pub fn offsets(&self) -> Vec<[i64; 2]> {
+        match *self {
+            Self::Standard(count) => {
+                let positions: &[[i64; 2]] = match count {
+                    1 => &STANDARD_1,
+                    2 => &STANDARD_2,
+                    4 => &STANDARD_4,
+                    8 => &STANDARD_8,
+                    16 => &STANDARD_16,
+                    _ => panic!("there's no standard pattern for {} samples", count),
+                };
+                positions
+                    .iter()
+                    .map(|position| position.map(|v| v << (SUBPIXEL_BITS - 4)))
+                    .collect()
+            }
+            Self::OrderedGrid(n) => {
+                assert!(
+                    (1..=MAX_SAMPLES).contains(&self.count()),
+                    "a {}x{} grid doesn't fit in a coverage mask",
+                    n,
+                    n
+                );
+                // The centers of n by n equal cells, rounded to the nearest
+                // subpixel
+                let n = i64::from(n);
+                let offset = |i: i64| (((2 * i + 1) << SUBPIXEL_BITS) + n) / (2 * n);
+                (0..n)
+                    .flat_map(|y| (0..n).map(move |x| [offset(x), offset(y)]))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// How often [`draw_multisampled`] runs the fragment shader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shading {
+    /// Once per pixel, at its center, with the color written to every sample
+    /// the triangle covers. This is multisample anti-aliasing as the GPU does
+    /// it: edges are smoothed, but not the shading inside triangles.
+    #[default]
+    PerPixel,
+    /// Once for each covered sample, at the sample. This is supersampling,
+    /// which smooths textures and shading too, for as many times the work.
+    PerSample,
+}
+
+/// A framebuffer with several color and depth samples for each pixel, which
+/// are averaged into the final image by [`MultisampleFramebuffer::resolve`].
+#[derive(Clone, Debug)]
+pub struct MultisampleFramebuffer {
+    pattern: SamplePattern,
+    offsets: Vec<[i64; 2]>,
+    /// A full-size framebuffer for each sample, in the pattern's order.
+    pub samples: Vec<Framebuffer>,
+}
+
+impl MultisampleFramebuffer {
+    /// Creates a framebuffer cleared to transparent black and the far plane.
+    ///
+    /// # Panics
+    ///
+    /// If `pattern` isn't valid, as for [`SamplePattern::offsets`].
+    pub fn new(width: u32, height: u32, pattern: SamplePattern) -> Self {
+        let offsets = pattern.offsets();
+        Self {
+            pattern,
+            samples: vec![Framebuffer::new(width, height); offsets.len()],
+            offsets,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.samples[0].width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.samples[0].height()
+    }
+
+    pub fn pattern(&self) -> SamplePattern {
+        self.pattern
+    }
+
+    /// Clears every sample, as [`Framebuffer::clear`] does.
+    pub fn clear(&mut self, color: Vec4) {
+        self.samples
+            .iter_mut()
+            .for_each(|sample| sample.clear(color));
+    }
+
+    /// Averages the samples of each pixel. Like the GPU resolving an sRGB
+    /// target, colors are averaged in linear space and encoded again.
+    pub fn resolve(&self) -> RgbaImage {
+        let scale = 1.0 / self.samples.len() as f32;
+        RgbaImage::from_fn(self.width(), self.height(), |x, y| {
+            let mut sum = Vec4::ZERO;
+            for sample in &self.samples {
+                let Rgba([r, g, b, a]) = *sample.color.get_pixel(x, y);
+                sum += Vec4::new(
+                    srgb_to_linear(r),
+                    srgb_to_linear(g),
+                    srgb_to_linear(b),
+                    f32::from(a) / 255.0,
+                );
+            }
+            let mean = sum * scale;
+            Rgba([
+                linear_to_srgb(mean.x),
+                linear_to_srgb(mean.y),
+                linear_to_srgb(mean.z),
+                (mean.w * 255.0).round() as u8,
+            ])
+        })
+    }
+}
+
+/// Draws like [`draw`](super::raster::draw), but tests coverage and depth at
+/// each of the target's samples, and writes the color to the samples that
+/// pass. With [`SamplePattern::Standard`] and [`Shading::PerPixel`], this
+/// matches the engine's pipeline with the same number of samples.
+pub fn draw_multisampled<S: Shader>(
+    target: &mut MultisampleFramebuffer,
+    shader: &S,
+    vertices: &[S::Vertex],
+    indices: &[u32],
+    shading: Shading,
+) {
+    let (width, height) = (target.width(), target.height());
+    let size = Vec2::new(width as f32, height as f32);
+    let shaded: Vec<_> = vertices.iter().map(|v| shader.vertex(v)).collect();
+    for triangle in indices.chunks_exact(3) {
+        let corners = [0, 1, 2].map(|i| shaded[triangle[i] as usize]);
+        for triangle in clip(corners).filter_map(|corners| Triangle::new(corners, size)) {
+            let (xs, ys) = triangle.sample_bounds(width, height, &target.offsets);
+            for y in ys {
+                for x in xs.clone() {
+                    shade_pixel(target, shader, &triangle, x, y, shading);
+                }
+            }
+        }
+    }
+}
+
+/// Draws the samples of pixel `x`, `y` that `triangle` covers and is in
+/// front of.
+fn shade_pixel<S: Shader>(
+    target: &mut MultisampleFramebuffer,
+    shader: &S,
+    triangle: &Triangle<S::Varyings>,
+    x: u32,
+    y: u32,
+    shading: Shading,
+) {
+    let MultisampleFramebuffer {
+        offsets, samples, ..
+    } = target;
+    let origin = [i64::from(x) << SUBPIXEL_BITS, i64::from(y) << SUBPIXEL_BITS];
+    let point = |offset: [i64; 2]| [origin[0] + offset[0], origin[1] + offset[1]];
+    let mut mask = 0u32;
+    let mut depths = [0.0; MAX_SAMPLES];
+    for (i, &offset) in offsets.iter().enumerate() {
+        let values = triangle.edge_values(point(offset));
+        if !triangle.covers(values) {
+            continue;
+        }
+        let z = triangle.depth(triangle.weights(values));
+        if z <= samples[i].depth.get_pixel(x, y).0[0] {
+            mask |= 1 << i;
+            depths[i] = z;
+        }
+    }
+    if mask == 0 {
+        return;
+    }
+    let covered = (0..samples.len()).filter(|i| mask & (1 << i) != 0);
+
+    let mut write = |i: usize, color: Vec4| {
+        samples[i].color.put_pixel(x, y, encode(color));
+        samples[i].depth.put_pixel(x, y, Luma([depths[i]]));
+    };
+    match shading {
+        Shading::PerPixel => {
+            let half = 1 << (SUBPIXEL_BITS - 1);
+            let weights = triangle.weights(triangle.edge_values(point([half; 2])));
+            let position =
+                Vec2::new(x as f32 + 0.5, y as f32 + 0.5).extend(triangle.depth(weights));
+            if let Some(color) = triangle.fragment(shader, position, weights) {
+                covered.for_each(|i| write(i, color));
+            }
+        }
+        Shading::PerSample => {
+            for i in covered {
+                let p = point(offsets[i]);
+                let weights = triangle.weights(triangle.edge_values(p));
+                let [px, py] = p.map(|v| v as f32 / (1 << SUBPIXEL_BITS) as f32);
+                let position = Vec2::new(px, py).extend(depths[i]);
+                if let Some(color) = triangle.fragment(shader, position, weights) {
+                    write(i, color);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::shader::Fragment;
+
+    /// Passes clip-space positions through and draws each triangle's color.
+    struct Flat;
+
+    impl Shader for Flat {
+        type Vertex = (Vec4, Vec4);
+        type Varyings = Vec4;
+
+        fn vertex(&self, &(position, color): &(Vec4, Vec4)) -> (Vec4, Vec4) {
+            (position, color)
+        }
+
+        fn fragment(&self, _: &Fragment, color: Vec4) -> Option<Vec4> {
+            Some(color)
+        }
+    }
+
+    const RED: Vec4 = Vec4::new(1.0, 0.0, 0.0, 1.0);
+    const WHITE: Vec4 = Vec4::ONE;
+
+    /// Draws a rectangle spanning clip-space x from `left` to `right` and
+    /// the full height of `target`, at depth `z`.
+    fn draw_rect(target: &mut MultisampleFramebuffer, left: f32, right: f32, z: f32, color: Vec4) {
+        let vertices = [[left, -1.0], [right, -1.0], [right, 1.0], [left, 1.0]]
+            .map(|[x, y]| (Vec4::new(x, y, z, 1.0), color));
+        draw_multisampled(
+            target,
+            &Flat,
+            &vertices,
+            &[0, 1, 2, 0, 2, 3],
+            Shading::PerPixel,
+        );
+    }
+
+    /// Whether sample `offset` is in the left half of its pixel.
+    fn is_left(offset: [i64; 2]) -> bool {
+        offset[0] < 1 << (SUBPIXEL_BITS - 1)
+    }
+
+    #[test]
+    fn half_covered_pixels_resolve_in_linear_space() {
+        let mut target = MultisampleFramebuffer::new(1, 1, SamplePattern::OrderedGrid(2));
+        target.clear(Vec4::new(0.0, 0.0, 0.0, 1.0));
+        // The left half of the only pixel
+        draw_rect(&mut target, -1.0, 0.0, 0.5, WHITE);
+        let half = linear_to_srgb(0.5);
+        assert_eq!(
+            *target.resolve().get_pixel(0, 0),
+            Rgba([half, half, half, 255])
+        );
+    }
+
+    #[test]
+    fn ordered_grids_are_symmetric() {
+        let one = 1 << SUBPIXEL_BITS;
+        for n in 1..=5 {
+            let offsets = SamplePattern::OrderedGrid(n).offsets();
+            assert_eq!(offsets.len(), (n * n) as usize);
+            for &[x, y] in &offsets {
+                for mirrored in [[one - x, y], [x, one - y]] {
+                    assert!(offsets.contains(&mirrored), "{:?} in {}x{}", mirrored, n, n);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_that_fail_the_depth_test_keep_their_color() {
+        let mut target = MultisampleFramebuffer::new(1, 1, SamplePattern::Standard(4));
+        target.clear(Vec4::ZERO);
+        draw_rect(&mut target, -1.0, 0.0, 0.25, RED);
+        // Behind the red half, and in front of the cleared one
+        draw_rect(&mut target, -1.0, 1.0, 0.75, WHITE);
+        for (sample, &offset) in target.samples.iter().zip(&target.offsets) {
+            let expected = if is_left(offset) { RED } else { WHITE };
+            assert_eq!(
+                *sample.color.get_pixel(0, 0),
+                encode(expected),
+                "{:?}",
+                offset
+            );
+        }
+    }
+}
@@ -0,0 +1,576 @@
+use std::ops::Range;
+
+use image::{ImageBuffer, Luma, Rgba, RgbaImage};
+
+use super::{
+    clip::clip,
+    math::{Vec2, Vec3, Vec4},
+    shader::{Fragment, Quad, Shader, Varying},
+};
+use crate::bake::linear_to_srgb;
+
+/// A single-channel image of depths from 0 to 1.
+pub type DepthImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// The color and depth targets drawn into on the CPU. Color is stored as
+/// sRGB, like the engine's `Rgba8UnormSrgb` targets, so shaders return linear
+/// colors and they're encoded when written.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    pub color: RgbaImage,
+    pub depth: DepthImage,
+}
+
+impl Framebuffer {
+    /// Creates a framebuffer cleared to transparent black and the far plane.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            color: RgbaImage::new(width, height),
+            depth: DepthImage::from_pixel(width, height, Luma([1.0])),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.color.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.color.height()
+    }
+
+    /// Fills the color target with `color`, given in linear space, and resets
+    /// the depth target to the far plane.
+    pub fn clear(&mut self, color: Vec4) {
+        let color = encode(color);
+        self.color.pixels_mut().for_each(|pixel| *pixel = color);
+        self.depth
+            .pixels_mut()
+            .for_each(|depth| *depth = Luma([1.0]));
+    }
+}
+
+/// Encodes a linear color for an sRGB target.
+pub fn encode(color: Vec4) -> Rgba<u8> {
+    Rgba([
+        linear_to_srgb(color.x),
+        linear_to_srgb(color.y),
+        linear_to_srgb(color.z),
+        (color.w.clamp(0.0, 1.0) * 255.0).round() as u8,
+    ])
+}
+
+/// How many bits of the fixed-point framebuffer coordinates are below the
+/// pixel. Corners are snapped to 1/256th of a pixel, as most GPUs do, so
+/// coverage is decided with exact integer arithmetic.
+pub const SUBPIXEL_BITS: u32 = 8;
+
+const SUBPIXELS: f32 = (1 << SUBPIXEL_BITS) as f32;
+
+/// The furthest corners can be from the framebuffer's origin, in pixels,
+/// before the products in the edge functions could overflow. Triangles from
+/// [`clip`] are well within it.
+const MAX_COORDINATE: f32 = (1 << 20) as f32;
+
+/// Maps a position in clip space onto a framebuffer of `size`, returning its
+/// position there, its depth and 1 / w. This is the viewport transform as
+/// WebGPU specifies it.
+pub fn viewport(position: Vec4, size: Vec2) -> Vec4 {
+    let inv_w = 1.0 / position.w;
+    let half = size * 0.5;
+    // Clip space has y up, the framebuffer has y down
+    Vec4::new(
+        position.x * inv_w * half.x + half.x,
+        -position.y * inv_w * half.y + half.y,
+        position.z * inv_w,
+        inv_w,
+    )
+}
+
+/// The center of pixel `x`, `y` in fixed point. Like WebGPU, pixel centers
+/// are at half coordinates.
+pub fn sample_point(x: u32, y: u32) -> [i64; 2] {
+    let half = 1 << (SUBPIXEL_BITS - 1);
+    [
+        (i64::from(x) << SUBPIXEL_BITS) + half,
+        (i64::from(y) << SUBPIXEL_BITS) + half,
+    ]
+}
+
+/// The function `a * x + b * y + c` of one edge of a triangle, in fixed
+/// point, which is positive on the triangle's side of the edge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub a: i64,
+    pub b: i64,
+    pub c: i64,
+    /// -1 unless this is a top or left edge, so that samples exactly on an
+    /// edge shared by two triangles are only covered by one of them.
+    pub bias: i64,
+}
+
+impl Edge {
+    /// The edge from `from` to `to` of a clockwise triangle on screen.
+    fn new(from: [i64; 2], to: [i64; 2]) -> Self {
+        let a = to[1] - from[1];
+        let b = from[0] - to[0];
+        // The interior is to the right of left edges, and below horizontal
+        // top edges
+        let top_left = a > 0 || (a == 0 && b > 0);
+        Self {
+            a,
+            b,
+            c: -(a * from[0] + b * from[1]),
+            bias: if top_left { 0 } else { -1 },
+        }
+    }
+
+    pub fn at(&self, p: [i64; 2]) -> i64 {
+        self.a * p[0] + self.b * p[1] + self.c
+    }
+
+    /// Whether a sample where the function is `value` is on the triangle's
+    /// side, following the top-left rule.
+    pub fn covers(&self, value: i64) -> bool {
+        value + self.bias >= 0
+    }
+}
+
+/// A triangle after the vertex shader, ready to rasterize.
+pub struct Triangle<V> {
+    /// Each corner's framebuffer position, depth and 1 / w.
+    pub corners: [Vec4; 3],
+    pub varyings: [V; 3],
+    /// The edges opposite each corner.
+    pub edges: [Edge; 3],
+    /// Twice the area in square subpixels.
+    pub area: i64,
+    /// The fixed-point bounding box of the corners.
+    min: [i64; 2],
+    max: [i64; 2],
+}
+
+impl<V: Varying> Triangle<V> {
+    /// Projects a triangle from clip space onto a framebuffer of `size`,
+    /// returning `None` if it can't be drawn: if it's degenerate once
+    /// snapped, faces away from the viewer, is entirely in front of the near
+    /// plane or behind the far plane, or crosses the plane w = 0. Triangles
+    /// from [`clip`] never cross it.
+    pub fn new(clip: [(Vec4, V); 3], size: Vec2) -> Option<Self> {
+        if clip.iter().any(|(position, _)| position.w <= 0.0)
+            || clip.iter().all(|(position, _)| position.z < 0.0)
+            || clip.iter().all(|(position, _)| position.z > position.w)
+        {
+            return None;
+        }
+        let corners = clip.map(|(position, _)| viewport(position, size));
+        // This also rejects NaN
+        if !corners
+            .iter()
+            .all(|corner| corner.x.abs() <= MAX_COORDINATE && corner.y.abs() <= MAX_COORDINATE)
+        {
+            return None;
+        }
+        // Ties round to even, as the GPU's float to integer conversions do
+        let points = corners.map(|corner| {
+            [
+                (corner.x * SUBPIXELS).round_ties_even() as i64,
+                (corner.y * SUBPIXELS).round_ties_even() as i64,
+            ]
+        });
+        let [a, b, c] = points;
+        let edges = [Edge::new(b, c), Edge::new(c, a), Edge::new(a, b)];
+        // Counter-clockwise triangles, which the engine treats as front
+        // facing, turn clockwise when y is flipped, and have a positive area
+        let area = edges[0].at(a);
+        if area <= 0 {
+            return None;
+        }
+        Some(Self {
+            corners,
+            varyings: clip.map(|(_, varyings)| varyings),
+            edges,
+            area,
+            min: [0, 1].map(|i| a[i].min(b[i]).min(c[i])),
+            max: [0, 1].map(|i| a[i].max(b[i]).max(c[i])),
+        })
+    }
+
+    /// The pixels whose centers the triangle might cover on a framebuffer of
+    /// `width` by `height`.
+    pub fn bounds(&self, width: u32, height: u32) -> (Range<u32>, Range<u32>) {
+        let half = 1 << (SUBPIXEL_BITS - 1);
+        self.sample_bounds(width, height, &[[half, half]])
+    }
+
+    /// Like [`Triangle::bounds`], for the pixels any of whose samples might
+    /// be covered, given the samples' fixed-point offsets from the top left
+    /// corner of their pixel.
+    pub fn sample_bounds(
+        &self,
+        width: u32,
+        height: u32,
+        offsets: &[[i64; 2]],
+    ) -> (Range<u32>, Range<u32>) {
+        let range = |axis: usize, size: u32| {
+            let low = offsets.iter().map(|offset| offset[axis]).min().unwrap_or(0);
+            let high = offsets.iter().map(|offset| offset[axis]).max().unwrap_or(0);
+            // The first and last pixels with a sample inside the box
+            let first = (self.min[axis] - high + (1 << SUBPIXEL_BITS) - 1) >> SUBPIXEL_BITS;
+            let last = (self.max[axis] - low) >> SUBPIXEL_BITS;
+            let start = first.clamp(0, size.into());
+            let end = (last + 1).clamp(start, size.into());
+            start as u32..end as u32
+        };
+        (range(0, width), range(1, height))
+    }
+
+    /// Evaluates the edge functions at a sample.
+    pub fn edge_values(&self, p: [i64; 2]) -> [i64; 3] {
+        self.edges.map(|edge| edge.at(p))
+    }
+
+    /// Whether a sample with the given edge values is covered.
+    pub fn covers(&self, values: [i64; 3]) -> bool {
+        (0..3).all(|i| self.edges[i].covers(values[i]))
+    }
+
+    /// The barycentric weights of a sample with the given edge values.
+    pub fn weights(&self, values: [i64; 3]) -> Vec3 {
+        Vec3::new(values[0] as f32, values[1] as f32, values[2] as f32) / self.area as f32
+    }
+
+    /// The depth of a sample with the given weights.
+    pub fn depth(&self, weights: Vec3) -> f32 {
+        let [a, b, c] = self.corners;
+        // Rounding can take the depth of a triangle on the near or far plane
+        // just past it, so clamp as the GPU does after clipping
+        (weights.x * a.z + weights.y * b.z + weights.z * c.z).clamp(0.0, 1.0)
+    }
+
+    /// Shades pixel `x`, `y` if the triangle covers its center and it passes
+    /// the depth test against `depth`, returning the color and depth to
+    /// write.
+    pub fn shade<S: Shader<Varyings = V>>(
+        &self,
+        shader: &S,
+        x: u32,
+        y: u32,
+        depth: f32,
+    ) -> Option<(Vec4, f32)> {
+        let values = self.edge_values(sample_point(x, y));
+        if !self.covers(values) {
+            return None;
+        }
+        self.shade_covered(shader, x, y, self.weights(values), depth)
+    }
+
+    /// Like [`Triangle::shade`], for a pixel already known to be covered
+    /// with the given `weights`.
+    pub fn shade_covered<S: Shader<Varyings = V>>(
+        &self,
+        shader: &S,
+        x: u32,
+        y: u32,
+        weights: Vec3,
+        depth: f32,
+    ) -> Option<(Vec4, f32)> {
+        let z = self.depth(weights);
+        if z > depth {
+            return None;
+        }
+        let position = Vec3::new(x as f32 + 0.5, y as f32 + 0.5, z);
+        self.fragment(shader, position, weights)
+            .map(|color| (color, z))
+    }
+
+    /// Runs the fragment shader at `position`, a point in the framebuffer
+    /// and its depth, with the varyings interpolated by `weights`, and at
+    /// the points a pixel to the right and below for [`Quad`]. The point
+    /// needn't be covered: the GPU shades multisampled pixels at their
+    /// centers even when only other samples are inside the triangle.
+    pub fn fragment<S: Shader<Varyings = V>>(
+        &self,
+        shader: &S,
+        position: Vec3,
+        weights: Vec3,
+    ) -> Option<Vec4> {
+        let [a, b, c] = self.corners;
+        let inv_ws = Vec3::new(a.w, b.w, c.w);
+        // Interpolating v / w and 1 / w linearly on screen and dividing gives
+        // the perspective-correct v
+        let interpolate = |weights: Vec3| {
+            let perspective = weights * inv_ws;
+            let inv_w = perspective.x + perspective.y + perspective.z;
+            (V::interpolate(self.varyings, perspective / inv_w), inv_w)
+        };
+        // The weights are linear on screen, and step by the edge functions'
+        // coefficients from one pixel to the next
+        let step = |coefficient: fn(&Edge) -> i64| {
+            let [e0, e1, e2] = self.edges.map(|edge| coefficient(&edge) as f32);
+            Vec3::new(e0, e1, e2) * (SUBPIXELS / self.area as f32)
+        };
+        let (here, inv_w) = interpolate(weights);
+        let varyings = Quad {
+            here,
+            right: interpolate(weights + step(|edge| edge.a)).0,
+            below: interpolate(weights + step(|edge| edge.b)).0,
+        };
+        let fragment = Fragment {
+            position: position.extend(inv_w),
+            front_facing: true,
+        };
+        shader.fragment_quad(&fragment, varyings)
+    }
+}
+
+/// Draws an indexed triangle list with `shader`, visiting every pixel in each
+/// triangle's bounding box in turn. This is the reference the faster
+/// rasterizers are checked against.
+///
+/// Like the engine's pipeline, triangles are clipped to the near and far
+/// planes, clockwise triangles are culled, pixels on shared edges are drawn
+/// once by the top-left rule, and fragments are depth tested with
+/// `LessEqual` against `target.depth`, as they are against the engine's
+/// depth buffer.
+pub fn draw<S: Shader>(
+    target: &mut Framebuffer,
+    shader: &S,
+    vertices: &[S::Vertex],
+    indices: &[u32],
+) {
+    let size = Vec2::new(target.width() as f32, target.height() as f32);
+    let shaded: Vec<_> = vertices.iter().map(|v| shader.vertex(v)).collect();
+    for triangle in indices.chunks_exact(3) {
+        let corners = [0, 1, 2].map(|i| shaded[triangle[i] as usize]);
+        for triangle in clip(corners).filter_map(|corners| Triangle::new(corners, size)) {
+            let (xs, ys) = triangle.bounds(target.width(), target.height());
+            for y in ys {
+                for x in xs.clone() {
+                    let depth = target.depth.get_pixel(x, y).0[0];
+                    if let Some((color, z)) = triangle.shade(shader, x, y, depth) {
+                        target.color.put_pixel(x, y, encode(color));
+                        target.depth.put_pixel(x, y, Luma([z]));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    const SIZE: u32 = 64;
+
+    /// Counts how many times each pixel is shaded. Vertices are given in
+    /// framebuffer coordinates, which map back exactly onto the same
+    /// subpixels since the framebuffer's size is a power of two.
+    struct Counter {
+        counts: Vec<AtomicU32>,
+    }
+
+    impl Counter {
+        fn new() -> Self {
+            Self {
+                counts: (0..SIZE * SIZE).map(|_| AtomicU32::new(0)).collect(),
+            }
+        }
+
+        fn count(&self, x: u32, y: u32) -> u32 {
+            self.counts[(y * SIZE + x) as usize].load(Ordering::Relaxed)
+        }
+    }
+
+    impl Shader for Counter {
+        type Vertex = Vec2;
+        type Varyings = ();
+
+        fn vertex(&self, p: &Vec2) -> (Vec4, ()) {
+            let half = SIZE as f32 / 2.0;
+            (Vec4::new(p.x / half - 1.0, 1.0 - p.y / half, 0.5, 1.0), ())
+        }
+
+        fn fragment(&self, fragment: &Fragment, _: ()) -> Option<Vec4> {
+            let (x, y) = (fragment.position.x as u32, fragment.position.y as u32);
+            self.counts[(y * SIZE + x) as usize].fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    /// A small deterministic jitter of whole subpixels, under a quarter of a
+    /// pixel either way.
+    fn jitter(seed: u32) -> f32 {
+        let hash = seed.wrapping_mul(2_654_435_761) >> 16;
+        ((hash % 127) as f32 - 63.0) / SUBPIXELS
+    }
+
+    /// Appends `triangle`, turned to face the viewer.
+    fn push(indices: &mut Vec<u32>, vertices: &[Vec2], [a, b, c]: [u32; 3]) {
+        let [pa, pb, pc] = [a, b, c].map(|i| vertices[i as usize]);
+        // Front faces are clockwise on screen, where y points down
+        let area = (pb.x - pa.x) * (pc.y - pa.y) - (pc.x - pa.x) * (pb.y - pa.y);
+        indices.extend(if area < 0.0 { [a, b, c] } else { [a, c, b] });
+    }
+
+    /// A `cells` by `cells` grid of triangles filling the rectangle from
+    /// `min` to `max`, with the inner vertices jittered so the shared edges
+    /// run at all angles.
+    fn grid(min: Vec2, max: Vec2, cells: u32) -> (Vec<Vec2>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        for j in 0..=cells {
+            for i in 0..=cells {
+                let t = Vec2::new(i as f32, j as f32) / cells as f32;
+                let mut p = min + (max - min) * t;
+                if i % cells != 0 {
+                    p.x += jitter(j * 31 + i);
+                }
+                if j % cells != 0 {
+                    p.y += jitter(j * 17 + i * 7 + 1);
+                }
+                vertices.push(p);
+            }
+        }
+        let mut indices = Vec::new();
+        let index = |i: u32, j: u32| j * (cells + 1) + i;
+        for j in 0..cells {
+            for i in 0..cells {
+                let [a, b, c, d] = [
+                    index(i, j),
+                    index(i + 1, j),
+                    index(i, j + 1),
+                    index(i + 1, j + 1),
+                ];
+                // Alternate the diagonals so vertices are shared by up to
+                // eight triangles
+                if (i + j) % 2 == 0 {
+                    push(&mut indices, &vertices, [a, b, d]);
+                    push(&mut indices, &vertices, [a, d, c]);
+                } else {
+                    push(&mut indices, &vertices, [a, b, c]);
+                    push(&mut indices, &vertices, [b, d, c]);
+                }
+            }
+        }
+        (vertices, indices)
+    }
+
+    /// A fan of triangles around a point inside the rectangle from `min` to
+    /// `max`, out to points spaced along its sides.
+    fn fan(min: Vec2, max: Vec2, center: Vec2, per_side: u32) -> (Vec<Vec2>, Vec<u32>) {
+        let mut vertices = vec![center];
+        let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+        for side in 0..4 {
+            let (from, to) = (corners[side], corners[(side + 1) % 4]);
+            for i in 0..per_side {
+                vertices.push(from.lerp(to, i as f32 / per_side as f32));
+            }
+        }
+        let rim = vertices.len() as u32 - 1;
+        let mut indices = Vec::new();
+        for i in 0..rim {
+            push(&mut indices, &vertices, [0, 1 + i, 1 + (i + 1) % rim]);
+        }
+        (vertices, indices)
+    }
+
+    /// Draws the mesh, which exactly covers the rectangle from `min` to
+    /// `max`, and checks that every pixel whose center is inside is shaded
+    /// once and no other pixel is. By the top-left rule, centers on the
+    /// rectangle's left and top sides are inside, and those on its right
+    /// and bottom sides aren't.
+    fn assert_covered_once(min: Vec2, max: Vec2, (vertices, indices): (Vec<Vec2>, Vec<u32>)) {
+        let counter = Counter::new();
+        let mut target = Framebuffer::new(SIZE, SIZE);
+        draw(&mut target, &counter, &vertices, &indices);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let inside =
+                    min.x <= center.x && center.x < max.x && min.y <= center.y && center.y < max.y;
+                assert_eq!(
+                    counter.count(x, y),
+                    u32::from(inside),
+                    "pixel {}, {} of the mesh covering {:?} to {:?}",
+                    x,
+                    y,
+                    min,
+                    max
+                );
+            }
+        }
+    }
+
+    /// Offsets of the meshes from whole pixels, including half a pixel, which
+    /// puts the rectangle's sides and the grid's outer vertices on pixel
+    /// centers.
+    const OFFSETS: [f32; 6] = [0.0, 0.5, 1.0 / 256.0, 127.0 / 256.0, 129.0 / 256.0, 0.75];
+
+    #[test]
+    fn grid_covers_each_pixel_once() {
+        for offset in OFFSETS {
+            for cells in [1, 4, 7] {
+                let (min, max) = (Vec2::splat(8.0 + offset), Vec2::splat(56.0 + offset));
+                assert_covered_once(min, max, grid(min, max, cells));
+            }
+        }
+    }
+
+    #[test]
+    fn fan_covers_each_pixel_once() {
+        for offset in OFFSETS {
+            let (min, max) = (
+                Vec2::new(4.0, 10.0) + Vec2::splat(offset),
+                Vec2::new(60.0, 50.0) + Vec2::splat(offset),
+            );
+            // A center on a pixel center, so every spoke passes through it
+            let center = Vec2::new(30.5, 30.5);
+            for per_side in [1, 3, 8] {
+                assert_covered_once(min, max, fan(min, max, center, per_side));
+            }
+        }
+    }
+
+    #[test]
+    fn axis_aligned_edges_on_pixel_centers() {
+        // Two triangles sharing a vertical edge at x = 20.5 and two sharing
+        // a horizontal edge at y = 40.5, both through a column and row of
+        // pixel centers
+        let vertices = [(20.5, 10.0), (20.5, 30.0), (10.0, 20.0), (30.0, 20.0)]
+            .map(|(x, y)| Vec2::new(x, y))
+            .to_vec();
+        let mut indices = Vec::new();
+        push(&mut indices, &vertices, [0, 1, 2]);
+        push(&mut indices, &vertices, [0, 1, 3]);
+        let horizontal =
+            [(30.0, 40.5), (50.0, 40.5), (40.0, 34.0), (40.0, 47.0)].map(|(x, y)| Vec2::new(x, y));
+        let base = vertices.len() as u32;
+        let mut vertices = vertices;
+        vertices.extend(horizontal);
+        push(&mut indices, &vertices, [base, base + 1, base + 2]);
+        push(&mut indices, &vertices, [base, base + 1, base + 3]);
+
+        let counter = Counter::new();
+        let mut target = Framebuffer::new(SIZE, SIZE);
+        draw(&mut target, &counter, &vertices, &indices);
+        for y in 11..30 {
+            assert_eq!(
+                counter.count(20, y),
+                1,
+                "pixel 20, {} on the vertical edge",
+                y
+            );
+        }
+        for x in 31..50 {
+            assert_eq!(
+                counter.count(x, 40),
+                1,
+                "pixel {}, 40 on the horizontal edge",
+                x
+            );
+        }
+        assert!((0..SIZE * SIZE).all(|i| counter.counts[i as usize].load(Ordering::Relaxed) <= 1));
+    }
+}
@@ -0,0 +1,99 @@
+use image::{Rgba, RgbaImage};
+use wgpu::TextureFormat;
+
+use super::{
+    math::{Mat4, Vec4},
+    msaa::{draw_multisampled, MultisampleFramebuffer, SamplePattern, Shading},
+    shaders::Textured,
+    texture::Texture,
+};
+use crate::{
+    bake::TextureData,
+    config::Config,
+    engine::{Engine, Mesh, CLEAR_COLOR},
+    error::EngineError,
+    renderer::Renderer,
+};
+
+/// The reference [`Renderer`]: draws with [`Textured`] and the rasterizer
+/// the faster ones are checked against, taking as many samples per pixel,
+/// at the same positions, as the engine does with the same `--msaa`.
+#[derive(Clone, Debug)]
+pub struct CpuRenderer {
+    mesh: Mesh,
+    shader: Textured,
+    target: MultisampleFramebuffer,
+    frame: RgbaImage,
+}
+
+impl CpuRenderer {
+    /// Creates a renderer for `width` by `height` frames with `samples` per
+    /// pixel, which is 1, 2, 4, 8 or 16. Until a texture is uploaded, the
+    /// mesh is drawn white.
+    pub fn new(width: u32, height: u32, samples: u32) -> Self {
+        let white = RgbaImage::from_pixel(1, 1, Rgba([255; 4]));
+        Self {
+            mesh: Mesh::default(),
+            shader: Textured {
+                texture: Texture::from_image(&white.into(), false),
+                camera: Mat4::IDENTITY,
+            },
+            target: MultisampleFramebuffer::new(width, height, SamplePattern::Standard(samples)),
+            frame: RgbaImage::new(width, height),
+        }
+    }
+
+    /// Creates a renderer with the surface size and sample count `config`
+    /// asks the engine for. The engine falls back to 1 sample where the
+    /// adapter can't multisample, so use [`CpuRenderer::for_engine`] to
+    /// compare against one.
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.surface_width, config.surface_height, config.msaa)
+    }
+
+    /// Creates a renderer with the size and sample count `engine` actually
+    /// draws with.
+    pub fn for_engine(engine: &Engine) -> Self {
+        let size = engine.size();
+        Self::new(size.width, size.height, engine.sample_count())
+    }
+}
+
+impl Renderer for CpuRenderer {
+    fn upload_mesh(&mut self, mesh: &Mesh) {
+        self.mesh = mesh.clone();
+    }
+
+    async fn upload_texture(&mut self, texture: &TextureData) -> Result<(), EngineError> {
+        // The engine uploads every texture as sRGB
+        self.shader.texture = Texture::from_texture_data(texture, TextureFormat::Rgba8UnormSrgb);
+        Ok(())
+    }
+
+    fn set_camera(&mut self, view_proj: Mat4) {
+        self.shader.camera = view_proj;
+    }
+
+    fn draw(&mut self) -> Result<(), EngineError> {
+        let color = CLEAR_COLOR;
+        self.target.clear(Vec4::new(
+            color.r as f32,
+            color.g as f32,
+            color.b as f32,
+            color.a as f32,
+        ));
+        draw_multisampled(
+            &mut self.target,
+            &self.shader,
+            &self.mesh.vertices,
+            &self.mesh.indices,
+            Shading::PerPixel,
+        );
+        self.frame = self.target.resolve();
+        Ok(())
+    }
+
+    async fn read_back(&mut self) -> Result<RgbaImage, EngineError> {
+        Ok(self.frame.clone())
+    }
+}
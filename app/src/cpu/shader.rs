@@ -0,0 +1,98 @@
+use super::math::{Vec2, Vec3, Vec4};
+
+/// A value passed from [`Shader::vertex`] to [`Shader::fragment`], which is
+/// interpolated across each triangle like a WGSL `@location` output.
+///
+/// Implemented for floats, vectors, arrays and tuples of them; structs of
+/// varyings implement it by interpolating each field.
+pub trait Varying: Copy + Send + Sync {
+    /// Weighs the values at the three corners of a triangle. The weights sum
+    /// to 1, and are already perspective-correct.
+    fn interpolate(values: [Self; 3], weights: Vec3) -> Self;
+}
+
+macro_rules! linear_varying {
+    ($($ty:ty),+) => {
+        $(
+            impl Varying for $ty {
+                fn interpolate([a, b, c]: [Self; 3], weights: Vec3) -> Self {
+                    a * weights.x + b * weights.y + c * weights.z
+                }
+            }
+        )+
+    };
+}
+
+linear_varying!(f32, Vec2, Vec3, Vec4);
+
+impl Varying for () {
+    fn interpolate(_: [Self; 3], _: Vec3) -> Self {}
+}
+
+impl<T: Varying, const N: usize> Varying for [T; N] {
+    fn interpolate([a, b, c]: [Self; 3], weights: Vec3) -> Self {
+        std::array::from_fn(|i| T::interpolate([a[i], b[i], c[i]], weights))
+    }
+}
+
+macro_rules! tuple_varying {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name: Varying),+> Varying for ($($name,)+) {
+            fn interpolate([a, b, c]: [Self; 3], weights: Vec3) -> Self {
+                ($($name::interpolate([a.$index, b.$index, c.$index], weights),)+)
+            }
+        }
+    };
+}
+
+tuple_varying!(A 0);
+tuple_varying!(A 0, B 1);
+tuple_varying!(A 0, B 1, C 2);
+tuple_varying!(A 0, B 1, C 2, D 3);
+
+/// What a fragment shader knows about the pixel it's shading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fragment {
+    /// Like WGSL's `@builtin(position)`: the pixel center in framebuffer
+    /// coordinates, the depth, and 1 / w.
+    pub position: Vec4,
+    pub front_facing: bool,
+}
+
+/// A fragment's varyings, and those interpolated at the centers of the
+/// pixels to its right and below it. The GPU shades pixels in 2x2 quads and
+/// takes `dpdx` and `dpdy`, and so the mip level `textureSample` picks, from
+/// the differences across them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quad<V> {
+    pub here: V,
+    pub right: V,
+    pub below: V,
+}
+
+/// The CPU counterpart of a WGSL vertex and fragment shader pair, and of
+/// tinyrenderer's `IShader`.
+///
+/// Uniforms are the fields of the implementing type, set before drawing. The
+/// shader is shared between the threads that draw, so it's `Sync` and only
+/// gets `&self`.
+pub trait Shader: Sync {
+    /// The per-vertex input, like a WGSL vertex input struct.
+    type Vertex: Sync;
+    type Varyings: Varying;
+
+    /// Transforms `vertex` into clip space, returning the position along with
+    /// the values to interpolate for the fragment shader.
+    fn vertex(&self, vertex: &Self::Vertex) -> (Vec4, Self::Varyings);
+
+    /// Shades one pixel, returning its color in linear space, or `None` to
+    /// discard it as WGSL's `discard` does.
+    fn fragment(&self, fragment: &Fragment, varyings: Self::Varyings) -> Option<Vec4>;
+
+    /// Like [`Shader::fragment`], for shaders that need the varyings'
+    /// screen-space derivatives. This is what the rasterizers call; by
+    /// default it ignores the neighbors and calls [`Shader::fragment`].
+    fn fragment_quad(&self, fragment: &Fragment, varyings: Quad<Self::Varyings>) -> Option<Vec4> {
+        self.fragment(fragment, varyings.here)
+    }
+}
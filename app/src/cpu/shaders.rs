@@ -0,0 +1,49 @@
+use super::{
+    math::{Mat4, Vec2, Vec3, Vec4},
+    shader::{Fragment, Quad, Shader},
+    texture::Texture,
+};
+use crate::engine::ModelVertex;
+
+/// The CPU version of `res/shaders/shader.wgsl`: positions are transformed
+/// into clip space by the camera, and each pixel is colored straight from
+/// the texture.
+///
+/// Like `textureSample`, the mip level is picked from how far the texture
+/// coordinates move across a pixel, and sampled with the texture's filters,
+/// so mipmapped textures are minified as they are on the GPU. The GPU takes
+/// its derivatives within 2x2 quads, so levels can differ slightly where
+/// they change from one pixel to the next.
+#[derive(Clone, Debug)]
+pub struct Textured {
+    pub texture: Texture,
+    /// The view and projection matrices combined, as in the `camera`
+    /// uniform.
+    pub camera: Mat4,
+}
+
+impl Shader for Textured {
+    type Vertex = ModelVertex;
+    type Varyings = Vec2;
+
+    fn vertex(&self, model: &ModelVertex) -> (Vec4, Vec2) {
+        let clip_position = self.camera * Vec3::from(model.position).extend(1.0);
+        (clip_position, Vec2::from(model.tex_coords))
+    }
+
+    /// Samples level 0, for lack of derivatives.
+    fn fragment(&self, _: &Fragment, tex_coords: Vec2) -> Option<Vec4> {
+        Some(self.texture.sample(tex_coords))
+    }
+
+    fn fragment_quad(&self, _: &Fragment, tex_coords: Quad<Vec2>) -> Option<Vec4> {
+        // The footprint of the pixel in texels, as the Vulkan spec computes
+        // the level of detail
+        let (width, height) = (self.texture.width() as f32, self.texture.height() as f32);
+        let texels = |d: Vec2| Vec2::new(d.x * width, d.y * height).length();
+        let dx = texels(tex_coords.right - tex_coords.here);
+        let dy = texels(tex_coords.below - tex_coords.here);
+        let lod = dx.max(dy).log2();
+        Some(self.texture.sample_level(tex_coords.here, lod))
+    }
+}
@@ -0,0 +1,197 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use wgpu::{AddressMode, FilterMode, TextureFormat};
+
+use super::math::{Vec2, Vec4};
+use crate::bake::{srgb_to_linear, TextureData};
+
+/// How a [`Texture`] is sampled, with the same meaning as the fields of
+/// [`wgpu::SamplerDescriptor`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sampler {
+    pub address_mode_u: AddressMode,
+    pub address_mode_v: AddressMode,
+    pub mag_filter: FilterMode,
+    pub min_filter: FilterMode,
+    pub mipmap_filter: FilterMode,
+}
+
+impl Default for Sampler {
+    /// Clamps to the edge and picks the nearest texel, like
+    /// [`crate::texture::Texture::from_image`].
+    fn default() -> Self {
+        Self {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+        }
+    }
+}
+
+/// An RGBA8 image and its mip chain, sampled the way the GPU samples the
+/// equivalent [`crate::texture::Texture`]: sRGB textures are decoded to
+/// linear values before filtering, and (0, 0) is the top left corner.
+#[derive(Clone, Debug)]
+pub struct Texture {
+    levels: Vec<RgbaImage>,
+    srgb: bool,
+    pub sampler: Sampler,
+}
+
+impl Texture {
+    /// Mirrors [`crate::texture::Texture::from_image`]: one level, sampled
+    /// nearest, and sRGB unless it's a normal map.
+    pub fn from_image(img: &DynamicImage, is_normal_map: bool) -> Self {
+        Self {
+            levels: vec![img.to_rgba8()],
+            srgb: !is_normal_map,
+            sampler: Sampler::default(),
+        }
+    }
+
+    /// Mirrors [`crate::texture::Texture::from_texture_data`]: every mip
+    /// level, nearest when magnified and trilinear when minified.
+    pub fn from_texture_data(data: &TextureData, format: TextureFormat) -> Self {
+        let levels = data
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, pixels)| {
+                let (width, height) = data.level_size(level);
+                RgbaImage::from_raw(width, height, pixels.clone())
+                    .expect("mip level has the wrong size")
+            })
+            .collect();
+        Self {
+            levels,
+            srgb: format.is_srgb(),
+            sampler: Sampler {
+                min_filter: FilterMode::Linear,
+                mipmap_filter: FilterMode::Linear,
+                ..Sampler::default()
+            },
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height()
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Like WGSL's `textureLoad`: the texel at `x`, `y` of `level`, which
+    /// must be in bounds.
+    pub fn load(&self, x: u32, y: u32, level: usize) -> Vec4 {
+        self.decode(self.levels[level].get_pixel(x, y))
+    }
+
+    /// Samples level 0, like WGSL's `textureSampleLevel` with a level of 0.
+    /// A texture alone has no derivatives to pick a level from, so this
+    /// always uses the magnification filter; use [`Texture::sample_level`]
+    /// with a level from a [`Quad`](super::shader::Quad) to sample a smaller
+    /// one.
+    pub fn sample(&self, uv: Vec2) -> Vec4 {
+        self.sample_level(uv, 0.0)
+    }
+
+    /// Like WGSL's `textureSampleLevel`: samples at level of detail `lod`,
+    /// using the magnification filter at 0 and below and the minification
+    /// and mipmap filters above.
+    pub fn sample_level(&self, uv: Vec2, lod: f32) -> Vec4 {
+        if lod <= 0.0 {
+            return self.sample_in(0, uv, self.sampler.mag_filter);
+        }
+        let max_level = (self.levels.len() - 1) as f32;
+        let lod = lod.min(max_level);
+        match self.sampler.mipmap_filter {
+            FilterMode::Nearest => {
+                // Rounds half down, as the Vulkan spec does
+                let level = (lod + 0.5).ceil() - 1.0;
+                self.sample_in(level as usize, uv, self.sampler.min_filter)
+            }
+            FilterMode::Linear => {
+                let level = lod.floor();
+                let a = self.sample_in(level as usize, uv, self.sampler.min_filter);
+                if level == max_level {
+                    return a;
+                }
+                let b = self.sample_in(level as usize + 1, uv, self.sampler.min_filter);
+                a.lerp(b, lod - level)
+            }
+        }
+    }
+
+    fn sample_in(&self, level: usize, uv: Vec2, filter: FilterMode) -> Vec4 {
+        let image = &self.levels[level];
+        let (width, height) = (image.width() as i64, image.height() as i64);
+        let x = uv.x * width as f32;
+        let y = uv.y * height as f32;
+        let texel = |x: i64, y: i64| {
+            let x = address(x, width, self.sampler.address_mode_u);
+            let y = address(y, height, self.sampler.address_mode_v);
+            match (x, y) {
+                (Some(x), Some(y)) => self.decode(image.get_pixel(x, y)),
+                // The border color is transparent black
+                _ => Vec4::ZERO,
+            }
+        };
+        match filter {
+            FilterMode::Nearest => texel(x.floor() as i64, y.floor() as i64),
+            FilterMode::Linear => {
+                // Texel centers are at half coordinates
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let top = texel(x0, y0).lerp(texel(x0 + 1, y0), fx);
+                let bottom = texel(x0, y0 + 1).lerp(texel(x0 + 1, y0 + 1), fx);
+                top.lerp(bottom, fy)
+            }
+        }
+    }
+
+    fn decode(&self, &Rgba([r, g, b, a]): &Rgba<u8>) -> Vec4 {
+        let unorm = |v: u8| f32::from(v) / 255.0;
+        if self.srgb {
+            Vec4::new(
+                srgb_to_linear(r),
+                srgb_to_linear(g),
+                srgb_to_linear(b),
+                unorm(a),
+            )
+        } else {
+            Vec4::new(unorm(r), unorm(g), unorm(b), unorm(a))
+        }
+    }
+}
+
+/// Applies `mode` to texel coordinate `i` of a level `size` texels across,
+/// returning `None` for the border.
+fn address(i: i64, size: i64, mode: AddressMode) -> Option<u32> {
+    let i = match mode {
+        AddressMode::ClampToEdge => i.clamp(0, size - 1),
+        AddressMode::Repeat => i.rem_euclid(size),
+        AddressMode::MirrorRepeat => {
+            let i = i.rem_euclid(2 * size);
+            if i < size {
+                i
+            } else {
+                2 * size - 1 - i
+            }
+        }
+        AddressMode::ClampToBorder => {
+            if !(0..size).contains(&i) {
+                return None;
+            }
+            i
+        }
+    };
+    Some(i as u32)
+}
@@ -0,0 +1,310 @@
+use std::ops::Range;
+
+use image::{GenericImage, GenericImageView, Luma};
+use rayon::prelude::*;
+
+use super::{
+    clip::clip,
+    math::Vec2,
+    raster::{encode, sample_point, Framebuffer, Triangle, SUBPIXEL_BITS},
+    shader::{Shader, Varying},
+};
+
+/// The width and height of the square tiles triangles are binned into.
+pub const TILE_SIZE: u32 = 32;
+
+/// How many pixels of a row are tested against a triangle's edges at once.
+/// The edge functions are evaluated lane by lane, by adding multiples of
+/// their step across a pixel, so the compiler can vectorize them.
+const LANES: usize = 8;
+
+/// How many triangles each thread bins at a time.
+const BIN_CHUNK: usize = 1024;
+
+/// A triangle that touches a tile.
+#[derive(Clone, Copy, Debug)]
+struct Binned {
+    triangle: u32,
+    /// Whether the triangle covers the whole tile, so [`shade_tile`] can
+    /// trivially accept its pixels without testing them against the edges.
+    covers: bool,
+}
+
+/// Draws like [`draw`](super::raster::draw), but bins the triangles into
+/// tiles of [`TILE_SIZE`] pixels and shades the tiles in parallel.
+///
+/// Each tile draws its triangles in the order they're listed, and coverage
+/// is decided with the same exact arithmetic as the reference, so the output
+/// is identical to [`draw`](super::raster::draw)'s however many threads
+/// there are.
+pub fn draw_tiled<S: Shader>(
+    target: &mut Framebuffer,
+    shader: &S,
+    vertices: &[S::Vertex],
+    indices: &[u32],
+) {
+    let (width, height) = (target.width(), target.height());
+    let size = Vec2::new(width as f32, height as f32);
+    let shaded: Vec<_> = vertices.par_iter().map(|v| shader.vertex(v)).collect();
+    let triangles: Vec<_> = indices
+        .par_chunks_exact(3)
+        .flat_map_iter(|triangle| {
+            let corners = [0, 1, 2].map(|i| shaded[triangle[i] as usize]);
+            clip(corners).filter_map(move |corners| Triangle::new(corners, size))
+        })
+        .collect();
+
+    let tiles_x = width.div_ceil(TILE_SIZE);
+    let tiles_y = height.div_ceil(TILE_SIZE);
+    let tile_rect = |tile: u32| {
+        let x = tile % tiles_x * TILE_SIZE;
+        let y = tile / tiles_x * TILE_SIZE;
+        (
+            x..(x + TILE_SIZE).min(width),
+            y..(y + TILE_SIZE).min(height),
+        )
+    };
+
+    // Chunks of triangles are binned in parallel, then each tile reads its
+    // bins chunk by chunk so it still sees the triangles in order
+    let chunks: Vec<Vec<Vec<Binned>>> = triangles
+        .par_chunks(BIN_CHUNK)
+        .enumerate()
+        .map(|(chunk, triangles)| {
+            let mut bins = vec![Vec::new(); (tiles_x * tiles_y) as usize];
+            for (i, triangle) in triangles.iter().enumerate() {
+                let (xs, ys) = triangle.bounds(width, height);
+                if xs.is_empty() || ys.is_empty() {
+                    continue;
+                }
+                let index = (chunk * BIN_CHUNK + i) as u32;
+                for tile_y in ys.start / TILE_SIZE..ys.end.div_ceil(TILE_SIZE) {
+                    for tile_x in xs.start / TILE_SIZE..xs.end.div_ceil(TILE_SIZE) {
+                        let tile = tile_y * tiles_x + tile_x;
+                        let (xs, ys) = tile_rect(tile);
+                        if let Some(covers) = classify(triangle, xs, ys) {
+                            bins[tile as usize].push(Binned {
+                                triangle: index,
+                                covers,
+                            });
+                        }
+                    }
+                }
+            }
+            bins
+        })
+        .collect();
+
+    let target_ref = &*target;
+    let tiles: Vec<_> = (0..tiles_x * tiles_y)
+        .into_par_iter()
+        .filter_map(|tile| {
+            let mut bins = chunks.iter().flat_map(|bins| &bins[tile as usize]);
+            let first = bins.next()?;
+            let (xs, ys) = tile_rect(tile);
+            let mut buffer = Framebuffer {
+                color: target_ref
+                    .color
+                    .view(xs.start, ys.start, xs.len() as u32, ys.len() as u32)
+                    .to_image(),
+                depth: target_ref
+                    .depth
+                    .view(xs.start, ys.start, xs.len() as u32, ys.len() as u32)
+                    .to_image(),
+            };
+            for binned in std::iter::once(first).chain(bins) {
+                let triangle = &triangles[binned.triangle as usize];
+                shade_tile(&mut buffer, (xs.start, ys.start), shader, triangle, *binned);
+            }
+            Some((xs.start, ys.start, buffer))
+        })
+        .collect();
+
+    for (x, y, buffer) in tiles {
+        target
+            .color
+            .copy_from(&buffer.color, x, y)
+            .expect("tile is inside the framebuffer");
+        target
+            .depth
+            .copy_from(&buffer.depth, x, y)
+            .expect("tile is inside the framebuffer");
+    }
+}
+
+/// Tests the centers of a tile's corner pixels against a triangle's edges,
+/// returning `None` if they're all outside one of them, or whether they're
+/// all inside all of them. The edge functions are linear, so every pixel in
+/// the tile is then outside or inside too.
+fn classify<V: Varying>(triangle: &Triangle<V>, xs: Range<u32>, ys: Range<u32>) -> Option<bool> {
+    let corners = [
+        sample_point(xs.start, ys.start),
+        sample_point(xs.end - 1, ys.start),
+        sample_point(xs.start, ys.end - 1),
+        sample_point(xs.end - 1, ys.end - 1),
+    ];
+    let mut covers = true;
+    for edge in &triangle.edges {
+        let inside = corners
+            .iter()
+            .filter(|&&corner| edge.covers(edge.at(corner)))
+            .count();
+        if inside == 0 {
+            return None;
+        }
+        covers &= inside == corners.len();
+    }
+    Some(covers)
+}
+
+/// Draws the part of `triangle` that's inside `buffer`, a tile whose top
+/// left pixel is at `origin` in the framebuffer.
+///
+/// If the triangle covers the whole tile, every pixel is accepted without
+/// testing it, and the edge functions are only stepped along for the
+/// weights. Otherwise the pixels of each row are tested [`LANES`] at a time.
+fn shade_tile<S: Shader>(
+    buffer: &mut Framebuffer,
+    origin: (u32, u32),
+    shader: &S,
+    triangle: &Triangle<S::Varyings>,
+    binned: Binned,
+) {
+    let (xs, ys) = (
+        origin.0..origin.0 + buffer.width(),
+        origin.1..origin.1 + buffer.height(),
+    );
+    let mut shade = |x: u32, y: u32, values: [i64; 3]| {
+        let (bx, by) = (x - origin.0, y - origin.1);
+        let depth = buffer.depth.get_pixel(bx, by).0[0];
+        let weights = triangle.weights(values);
+        if let Some((color, z)) = triangle.shade_covered(shader, x, y, weights, depth) {
+            buffer.color.put_pixel(bx, by, encode(color));
+            buffer.depth.put_pixel(bx, by, Luma([z]));
+        }
+    };
+
+    if binned.covers {
+        let step = triangle.edges.map(|edge| edge.a << SUBPIXEL_BITS);
+        for y in ys {
+            let mut values = triangle.edge_values(sample_point(xs.start, y));
+            for x in xs.clone() {
+                shade(x, y, values);
+                values = std::array::from_fn(|edge| values[edge] + step[edge]);
+            }
+        }
+        return;
+    }
+
+    let (bxs, bys) = triangle.bounds(xs.end, ys.end);
+    let xs = bxs.start.max(xs.start)..bxs.end;
+    let ys = bys.start.max(ys.start)..bys.end;
+    // The edge functions step by a whole pixel from one lane to the next
+    let step: [i64; LANES] = std::array::from_fn(|i| (i as i64) << SUBPIXEL_BITS);
+    for y in ys {
+        for x in xs.clone().step_by(LANES) {
+            let lanes = (xs.end - x).min(LANES as u32) as usize;
+            let start = triangle.edge_values(sample_point(x, y));
+            let values: [[i64; LANES]; 3] = std::array::from_fn(|edge| {
+                let a = triangle.edges[edge].a;
+                std::array::from_fn(|i| start[edge] + a * step[i])
+            });
+            let covered: [bool; LANES] = std::array::from_fn(|i| {
+                (0..3).all(|edge| triangle.edges[edge].covers(values[edge][i]))
+            });
+            for i in (0..lanes).filter(|&i| covered[i]) {
+                shade(x + i as u32, y, values.map(|edge| edge[i]));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{
+        math::{Vec3, Vec4},
+        raster::draw,
+        shader::Fragment,
+    };
+
+    /// Passes clip-space positions through and draws each triangle's color.
+    struct Flat;
+
+    impl Shader for Flat {
+        type Vertex = (Vec4, Vec4);
+        type Varyings = Vec4;
+
+        fn vertex(&self, &(position, color): &(Vec4, Vec4)) -> (Vec4, Vec4) {
+            (position, color)
+        }
+
+        fn fragment(&self, _: &Fragment, color: Vec4) -> Option<Vec4> {
+            Some(color)
+        }
+    }
+
+    /// A deterministic value in 0-1.
+    fn random(seed: u32) -> f32 {
+        (seed.wrapping_mul(2_654_435_761) >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// More triangles than one bin chunk holds, at random depths: mostly
+    /// small ones, some a few tiles across, and a few that cover the whole
+    /// frame, some facing away. Together they bin triangles that touch,
+    /// straddle and fully cover tiles.
+    fn scene() -> (Vec<(Vec4, Vec4)>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        for i in 0..BIN_CHUNK as u32 * 2 + 100 {
+            let size = match i % 50 {
+                0 => 4.0,
+                1..=5 => 0.5,
+                _ => 0.1,
+            };
+            let center = Vec3::new(
+                random(i * 7) * 2.0 - 1.0,
+                random(i * 7 + 1) * 2.0 - 1.0,
+                0.0,
+            );
+            let color = Vec4::new(random(i * 7 + 2), random(i * 7 + 3), random(i * 7 + 4), 1.0);
+            let z = random(i * 7 + 5);
+            for corner in 0..3 {
+                let angle = random(i * 7 + 6) * 6.0 + corner as f32 * 2.1;
+                let offset = Vec3::new(angle.cos(), angle.sin(), 0.0) * size;
+                let mut position = (center + offset).extend(1.0);
+                position.z = z;
+                vertices.push((position, color));
+            }
+        }
+        let indices = (0..vertices.len() as u32).collect();
+        (vertices, indices)
+    }
+
+    #[test]
+    fn output_matches_the_reference_on_any_number_of_threads() {
+        let (vertices, indices) = scene();
+        let mut cleared = Framebuffer::new(150, 90);
+        cleared.clear(Vec4::new(0.0, 0.0, 0.0, 1.0));
+
+        let mut reference = cleared.clone();
+        draw(&mut reference, &Flat, &vertices, &indices);
+        for threads in [1, 2, 8] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let mut tiled = cleared.clone();
+            pool.install(|| draw_tiled(&mut tiled, &Flat, &vertices, &indices));
+            assert!(
+                tiled.color == reference.color,
+                "colors differ with {} threads",
+                threads
+            );
+            assert!(
+                tiled.depth == reference.depth,
+                "depths differ with {} threads",
+                threads
+            );
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+
+use image::{Rgba, RgbaImage};
+
+use super::{
+    clip::clip_line,
+    line::{line, Stroke},
+    math::{Mat4, Vec2, Vec3},
+    raster::viewport,
+};
+use crate::engine::Mesh;
+
+/// Draws the edges of every triangle in `mesh`, with its positions
+/// transformed into clip space by `transform`. Edges shared by two triangles
+/// are drawn once, so anti-aliased lines don't darken where they overlap.
+pub fn draw_wireframe(
+    image: &mut RgbaImage,
+    mesh: &Mesh,
+    transform: Mat4,
+    color: Rgba<u8>,
+    stroke: Stroke,
+) {
+    let size = Vec2::new(image.width() as f32, image.height() as f32);
+    // Vertices are split wherever texture coordinates are, so edges are
+    // told apart by their end points rather than their indices
+    let key = |index: u32| mesh.vertices[index as usize].position.map(f32::to_bits);
+    let mut drawn = HashSet::new();
+    for triangle in mesh.indices.chunks_exact(3) {
+        for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+            let (a, b) = (triangle[a], triangle[b]);
+            let (ka, kb) = (key(a), key(b));
+            if !drawn.insert(if ka <= kb { (ka, kb) } else { (kb, ka) }) {
+                continue;
+            }
+            let clip = |index: u32| {
+                transform * Vec3::from(mesh.vertices[index as usize].position).extend(1.0)
+            };
+            if let Some((from, to)) = clip_line(clip(a), clip(b)) {
+                let (from, to) = (viewport(from, size), viewport(to, size));
+                line(image, from.xy(), to.xy(), color, stroke);
+            }
+        }
+    }
+}
+
+/// An orthographic projection from the front, looking down -z, that fits the
+/// bounding box of `mesh` into a framebuffer with the given aspect ratio, as
+/// tinyrenderer draws its first wireframes.
+pub fn fit_orthographic(mesh: &Mesh, aspect: f32) -> Mat4 {
+    let positions = mesh.vertices.iter().map(|v| Vec3::from(v.position));
+    let min = positions.clone().fold(Vec3::splat(f32::MAX), Vec3::min);
+    let max = positions.fold(Vec3::splat(f32::MIN), Vec3::max);
+    if min.x > max.x {
+        return Mat4::IDENTITY;
+    }
+    let center = (min + max) * 0.5;
+    let extent = (max - min).max(Vec3::splat(f32::EPSILON));
+    // Leave a small margin, and keep the box between the near and far planes
+    let scale = 1.9 / (extent.x / aspect).max(extent.y);
+    Mat4::translation(Vec3::new(0.0, 0.0, 0.5))
+        * Mat4::scale(Vec3::new(scale / aspect, scale, -0.9 / extent.z))
+        * Mat4::translation(-center)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::math::Vec4, engine::ModelVertex};
+
+    #[test]
+    fn shared_edges_are_drawn_once() {
+        let corners = [[-0.8, -0.6], [0.7, -0.8], [0.6, 0.9], [-0.7, 0.5]];
+        // The diagonal's end points are split, as they are along a texture
+        // seam
+        let vertices = [0, 1, 2, 0, 2, 3].map(|i| {
+            let [x, y] = corners[i];
+            ModelVertex {
+                position: [x, y, 0.5],
+                tex_coords: [i as f32, 0.0],
+            }
+        });
+        let mesh = Mesh {
+            vertices: vertices.to_vec(),
+            indices: (0..6).collect(),
+        };
+        let color = Rgba([255, 255, 255, 128]);
+        let mut wireframe = RgbaImage::new(32, 32);
+        draw_wireframe(
+            &mut wireframe,
+            &mesh,
+            Mat4::IDENTITY,
+            color,
+            Stroke::AntiAliased,
+        );
+
+        // Each edge once, in the order the triangles reach them, since
+        // blending rounds differently in another order
+        let mut edges = RgbaImage::new(32, 32);
+        let point = |[x, y]: [f32; 2]| viewport(Vec4::new(x, y, 0.5, 1.0), Vec2::splat(32.0)).xy();
+        for (a, b) in [(0, 1), (1, 2), (2, 0), (2, 3), (3, 0)] {
+            let (from, to) = (point(corners[a]), point(corners[b]));
+            line(&mut edges, from, to, color, Stroke::AntiAliased);
+        }
+        assert!(wireframe == edges);
+    }
+}
@@ -1,26 +1,44 @@
-use std::{fmt, iter::once, sync::Arc};
+use std::{
+    fmt,
+    iter::once,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
 
-use anyhow::{Context, Result};
 use bytemuck::{cast_slice, Pod, Zeroable};
+use cfg_if::cfg_if;
 
-use wgpu::TextureSampleType;
+use image::RgbaImage;
 use wgpu::{
-    include_wgsl,
     util::{BufferInitDescriptor, DeviceExt},
-    vertex_attr_array, Backends, BindGroup, BindGroupDescriptor, BindGroupEntry,
-    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BlendComponent,
-    BlendState, Buffer, BufferAddress, BufferUsages, Color, ColorTargetState, ColorWrites,
-    CommandEncoderDescriptor, Device, DeviceDescriptor, Face, Features, FragmentState, FrontFace,
-    Instance, InstanceDescriptor, Limits, LoadOp, MultisampleState, Operations,
-    PipelineLayoutDescriptor, PolygonMode, PowerPreference, PrimitiveState, PrimitiveTopology,
-    Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
-    RenderPipelineDescriptor, RequestAdapterOptions, SamplerBindingType, ShaderStages, StoreOp,
-    Surface, SurfaceConfiguration, TextureUsages, TextureViewDescriptor, TextureViewDimension,
+    vertex_attr_array, Adapter, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindingResource, BlendComponent, BlendState, Buffer, BufferAddress,
+    BufferUsages, Color, ColorTargetState, ColorWrites, CommandEncoder, CommandEncoderDescriptor,
+    CompareFunction, DepthBiasState, DepthStencilState, Device, DeviceDescriptor, DeviceLostReason,
+    ErrorFilter, Face, Features, FilterMode, FragmentState, FrontFace, IndexFormat, Instance,
+    InstanceDescriptor, Limits, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, Queue, RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, RequestAdapterOptions, StencilState, StoreOp, Surface,
+    SurfaceConfiguration, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
     VertexAttribute, VertexBufferLayout, VertexState, VertexStepMode,
 };
 use winit::{dpi::PhysicalSize, window::Window};
 
-use crate::resources::load_texture;
+use crate::{
+    assets::AssetServer,
+    bake::{MeshData, TextureData},
+    capabilities::{AdapterReport, CapabilityReport, SurfaceReport},
+    config::Config,
+    cpu::{Mat4, Vec4},
+    error::EngineError,
+    reflect::ShaderReflection,
+    resources::{load_model, load_texture_data},
+    shader::{self, generated::shaders_shader as bindings, ShaderSource},
+    texture::Texture,
+};
 
 const MODEL_VERTEX_ATTRIBUTES: [VertexAttribute; 2] =
     vertex_attr_array![0 => Float32x3, 1 => Float32x2];
@@ -33,6 +51,12 @@ pub struct ModelVertex {
 }
 
 impl ModelVertex {
+    /// Where each attribute's field starts, by shader location.
+    const FIELD_OFFSETS: [(u32, usize); 2] = [
+        (0, std::mem::offset_of!(ModelVertex, position)),
+        (1, std::mem::offset_of!(ModelVertex, tex_coords)),
+    ];
+
     fn desc() -> VertexBufferLayout<'static> {
         use std::mem;
 
@@ -42,6 +66,71 @@ impl ModelVertex {
             attributes: &MODEL_VERTEX_ATTRIBUTES,
         }
     }
+
+    /// Checks that a shader reads vertices the way this struct lays them out.
+    fn check_layout(reflection: &ShaderReflection) -> Result<(), EngineError> {
+        reflection.check_vertex_layout(
+            "ModelVertex",
+            &Self::desc(),
+            std::mem::size_of::<Self>(),
+            &Self::FIELD_OFFSETS,
+        )
+    }
+}
+
+/// An indexed triangle list.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl From<MeshData> for Mesh {
+    fn from(data: MeshData) -> Self {
+        Self {
+            vertices: data
+                .vertices
+                .into_iter()
+                .map(|[x, y, z, u, v]| ModelVertex {
+                    position: [x, y, z],
+                    tex_coords: [u, v],
+                })
+                .collect(),
+            indices: data.indices,
+        }
+    }
+}
+
+impl Mesh {
+    /// The textured square from the introductory post, which covers the
+    /// whole of clip space on the far plane.
+    pub fn square() -> Self {
+        Self {
+            vertices: SQUARE_VERTICES.to_vec(),
+            indices: (0..SQUARE_VERTICES.len() as u32).collect(),
+        }
+    }
+
+    /// Writes the mesh over buffers created for a mesh of the same size with
+    /// [`BufferUsages::COPY_DST`]. Returns `false`, and writes nothing, if the
+    /// sizes differ.
+    pub fn write_buffers(
+        &self,
+        queue: &Queue,
+        vertex_buffer: &Buffer,
+        index_buffer: &Buffer,
+    ) -> bool {
+        let vertices: &[u8] = cast_slice(&self.vertices);
+        let indices: &[u8] = cast_slice(&self.indices);
+        if vertex_buffer.size() != vertices.len() as BufferAddress
+            || index_buffer.size() != indices.len() as BufferAddress
+        {
+            return false;
+        }
+        queue.write_buffer(vertex_buffer, 0, vertices);
+        queue.write_buffer(index_buffer, 0, indices);
+        true
+    }
 }
 
 const SQUARE_VERTICES: &[ModelVertex; 6] = &[
@@ -71,41 +160,266 @@ const SQUARE_VERTICES: &[ModelVertex; 6] = &[
     },
 ];
 
-pub struct Engine<'a> {
+/// Format used for offscreen targets when there is no surface to match.
+const HEADLESS_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+/// The color every frame starts from, in linear space.
+pub const CLEAR_COLOR: Color = Color {
+    r: 0.1,
+    g: 0.2,
+    b: 0.3,
+    a: 1.0,
+};
+
+/// CPU-side copies of everything the engine uploads to the GPU, kept so the
+/// GPU resources can be rebuilt after the device is lost.
+struct Scene {
+    shader: ShaderSource,
+    reflection: ShaderReflection,
+    texture: TextureData,
+    texture_label: String,
+    mesh: Mesh,
+    /// The model the mesh was loaded from, if it isn't the built-in square
+    /// or one set with [`Engine::set_mesh`].
+    mesh_label: Option<String>,
+    camera: Mat4,
+}
+
+/// State shared with the callbacks registered on a device.
+#[derive(Default)]
+struct DeviceStatus {
+    /// Set just before the device is dropped.
+    dropped: AtomicBool,
+    errors: Mutex<Vec<String>>,
+    lost: AtomicBool,
+}
+
+/// Everything that belongs to one device, and so has to be recreated along
+/// with it.
+struct GpuResources {
     bind_group: BindGroup,
+    bind_group_layout: BindGroupLayout,
+    camera_buffer: Buffer,
+    depth_view: TextureView,
     device: Device,
+    index_buffer: Buffer,
+    msaa_view: Option<TextureView>,
+    /// The target of [`Engine::render_offscreen`], created on first use.
+    offscreen: Option<Texture>,
     queue: Queue,
     render_pipeline: RenderPipeline,
-    surface: Surface<'a>,
+    status: Arc<DeviceStatus>,
+    texture: Texture,
     vertex_buffer: Buffer,
-    window: Arc<Window>,
+}
+
+pub struct Engine<'a> {
+    adapter: Adapter,
+    assets: AssetServer,
+    capabilities: CapabilityReport,
+    format: TextureFormat,
+    gpu: GpuResources,
+    height: u32,
+    sample_count: u32,
+    scene: Scene,
+    surface: Option<Surface<'a>>,
+    surface_config: Option<SurfaceConfiguration>,
+    width: u32,
+    window: Option<Arc<Window>>,
 }
 
 impl Engine<'_> {
-    pub async fn new(window: Arc<Window>, width: u32, height: u32) -> Result<Self> {
-        log::debug!("Testing logging");
+    /// Creates an engine that presents to `window`.
+    pub async fn new(window: Arc<Window>, config: &Config) -> Result<Self, EngineError> {
+        let instance = Self::create_instance(config);
+        let surface = instance.create_surface(window.clone())?;
+        Self::init(instance, Some(surface), Some(window), config).await
+    }
+
+    /// Creates an engine with no window or surface, whose frames are drawn
+    /// offscreen and read back with [`Engine::read_offscreen`].
+    pub async fn new_headless(config: &Config) -> Result<Self, EngineError> {
+        let instance = Self::create_instance(config);
+        Self::init(instance, None, None, config).await
+    }
 
-        let instance = Instance::new(InstanceDescriptor {
-            backends: Backends::all(),
+    fn create_instance(config: &Config) -> Instance {
+        Instance::new(InstanceDescriptor {
+            backends: config.backend.into(),
             ..Default::default()
-        });
+        })
+    }
 
-        let surface = instance.create_surface(window.clone())?;
+    async fn init<'a>(
+        instance: Instance,
+        surface: Option<Surface<'a>>,
+        window: Option<Arc<Window>>,
+        config: &Config,
+    ) -> Result<Engine<'a>, EngineError> {
+        let width = config.surface_width;
+        let height = config.surface_height;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut adapters = instance.enumerate_adapters(config.backend.into());
+        #[cfg(not(target_arch = "wasm32"))]
+        let adapter_reports: Vec<_> = adapters.iter().map(AdapterReport::new).collect();
+
+        let adapter = match &config.adapter {
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(name) => {
+                let pattern = name.to_lowercase();
+                let i = adapters
+                    .iter()
+                    .position(|a| {
+                        a.get_info().name.to_lowercase().contains(&pattern)
+                            && surface.as_ref().is_none_or(|s| a.is_surface_supported(s))
+                    })
+                    .ok_or_else(|| {
+                        EngineError::NoAdapter(format!("no usable adapter matches {:?}", name))
+                    })?;
+                adapters.swap_remove(i)
+            }
+            _ => instance
+                .request_adapter(&RequestAdapterOptions {
+                    power_preference: config.power_preference.into(),
+                    force_fallback_adapter: false,
+                    compatible_surface: surface.as_ref(),
+                })
+                .await
+                .ok_or_else(|| {
+                    EngineError::NoAdapter(format!(
+                        "no {:?} adapter is compatible with the surface",
+                        config.backend
+                    ))
+                })?,
+        };
+
+        let selected = AdapterReport::new(&adapter);
+        cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let adapter_reports = vec![selected.clone()];
+            }
+        }
+
+        let device_fut = Self::request_device(&adapter);
 
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                power_preference: PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
+        let surface_report = surface
+            .as_ref()
+            .map(|surface| {
+                let surface_caps = surface.get_capabilities(&adapter);
+                let chosen = config.surface_preferences().choose(&surface_caps)?;
+                Ok::<_, EngineError>(SurfaceReport {
+                    formats: surface_caps.formats,
+                    present_modes: surface_caps.present_modes,
+                    alpha_modes: surface_caps.alpha_modes,
+                    chosen,
+                })
             })
-            .await
-            .context("Requst for adapter failed")?;
+            .transpose()?;
+        let surface_config = surface_report.as_ref().map(|report| SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: report.chosen.format,
+            width,
+            height,
+            present_mode: report.chosen.present_mode,
+            alpha_mode: report.chosen.alpha_mode,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        });
+        let format = surface_config
+            .as_ref()
+            .map_or(HEADLESS_FORMAT, |config| config.format);
+
+        let sample_count = if [format, Texture::DEPTH_FORMAT].iter().all(|&format| {
+            adapter
+                .get_texture_format_features(format)
+                .flags
+                .sample_count_supported(config.msaa)
+        }) {
+            config.msaa
+        } else {
+            log::warn!(
+                "{}x MSAA is not supported for {:?}, disabling multisampling",
+                config.msaa,
+                format
+            );
+            1
+        };
 
+        let capabilities = CapabilityReport {
+            adapters: adapter_reports,
+            selected,
+            surface: surface_report,
+            sample_count,
+        };
+        log::info!("{}", capabilities);
+
+        let (device, queue) = device_fut.await?;
+        if let (Some(surface), Some(surface_config)) = (&surface, &surface_config) {
+            log::debug!(
+                "About to configure surface {:?} using config {:?}",
+                surface,
+                surface_config
+            );
+            surface.configure(&device, surface_config);
+        }
+
+        let shader = ShaderSource::builtin()?;
+        let reflection = shader.reflect()?;
+        ModelVertex::check_layout(&reflection)?;
+        log::debug!("Reflected {}:\n{}", shader.label, reflection);
+
+        let scene = Scene {
+            shader,
+            reflection,
+            texture: load_texture_data(&config.texture).await?,
+            texture_label: config.texture.clone(),
+            mesh: match &config.model {
+                Some(model) => load_model(model).await?,
+                None => Mesh::square(),
+            },
+            mesh_label: config.model.clone(),
+            camera: Mat4::IDENTITY,
+        };
+
+        let gpu = GpuResources::new(
+            device,
+            queue,
+            &scene,
+            format,
+            sample_count,
+            PhysicalSize::new(width, height),
+        )
+        .await?;
+
+        let r = Engine {
+            adapter,
+            assets: AssetServer::new(&gpu.device, &gpu.queue),
+            capabilities,
+            format,
+            gpu,
+            height,
+            sample_count,
+            scene,
+            surface,
+            surface_config,
+            width,
+            window,
+        };
+
+        println!("Initialized {}", r);
+
+        Ok(r)
+    }
+
+    fn request_device(
+        adapter: &Adapter,
+    ) -> impl std::future::Future<Output = Result<(Device, Queue), wgpu::RequestDeviceError>> {
         let supported_features = adapter.features();
         let webgpu_features = Features::all_webgpu_mask();
         let requested_webgpu_features = supported_features & webgpu_features;
 
-        let device_fut = adapter.request_device(
+        adapter.request_device(
             &DeviceDescriptor {
                 label: Some("Engine.device"),
                 required_features: requested_webgpu_features,
@@ -116,81 +430,531 @@ impl Engine<'_> {
                 },
             },
             None,
+        )
+    }
+
+    /// Whether the device has been lost and [`Engine::recover`] needs to be
+    /// called before anything else can be drawn.
+    pub fn is_device_lost(&self) -> bool {
+        self.gpu.status.lost.load(Ordering::Acquire)
+    }
+
+    /// Returns the errors reported by wgpu outside of any error scope since
+    /// the last call. Without this handler, wgpu would panic on them instead.
+    pub fn take_errors(&self) -> Vec<String> {
+        std::mem::take(&mut *self.gpu.status.errors.lock().unwrap())
+    }
+
+    /// Requests a new device from the same adapter and rebuilds the surface
+    /// configuration and every GPU resource from the retained CPU-side scene.
+    pub async fn recover(&mut self) -> Result<(), EngineError> {
+        log::warn!("Rebuilding GPU resources on a new device");
+        let (device, queue) = Self::request_device(&self.adapter).await?;
+        if let (Some(surface), Some(surface_config)) = (&self.surface, &self.surface_config) {
+            surface.configure(&device, surface_config);
+        }
+        self.gpu = GpuResources::new(
+            device,
+            queue,
+            &self.scene,
+            self.format,
+            self.sample_count,
+            self.size(),
+        )
+        .await?;
+        self.assets.reload(&self.gpu.device, &self.gpu.queue);
+        Ok(())
+    }
+
+    /// Validates `shader` and rebuilds the render pipeline with it. On failure
+    /// the current pipeline is left in place and the error, which includes
+    /// naga's diagnostics with line and column numbers, is returned. The new
+    /// shader has to use the same bindings as the old one, since the bind
+    /// group isn't rebuilt.
+    pub async fn reload_shader(&mut self, shader: ShaderSource) -> Result<(), EngineError> {
+        let reflection = shader.reflect()?;
+        ModelVertex::check_layout(&reflection)?;
+        if reflection.bind_group(0) != self.scene.reflection.bind_group(0) {
+            return Err(EngineError::Validation {
+                label: shader.label,
+                message: format!(
+                    "the bindings in group 0 changed; restart to use them\n{}",
+                    reflection
+                ),
+            });
+        }
+        self.gpu.render_pipeline = GpuResources::create_pipeline(
+            &self.gpu.device,
+            &self.gpu.bind_group_layout,
+            &shader,
+            self.format,
+            self.sample_count,
+        )
+        .await?;
+        self.scene.shader = shader;
+        self.scene.reflection = reflection;
+        Ok(())
+    }
+
+    /// Loads `path` again wherever it's used, for when it changes on disk.
+    /// The scene's texture and mesh are written into their existing GPU
+    /// resources if they keep their size, and replaced otherwise, rebuilding
+    /// the bind group for a new texture. Assets loaded through
+    /// [`Engine::assets`] are reloaded in the background. On failure the scene
+    /// is left as it was.
+    pub async fn reload_asset(&mut self, path: &str) -> Result<(), EngineError> {
+        let mut used = self.assets.reload_file(path);
+        if path == self.scene.texture_label {
+            let data = load_texture_data(path).await?;
+            self.gpu.update_texture(&data, path).await?;
+            self.scene.texture = data;
+            used = true;
+        }
+        if self.scene.mesh_label.as_deref() == Some(path) {
+            let mesh = load_model(path).await?;
+            self.gpu.update_mesh(&mesh);
+            self.scene.mesh = mesh;
+            used = true;
+        }
+        if !used {
+            log::debug!("{} changed, but nothing uses it", path);
+        }
+        Ok(())
+    }
+
+    /// Replaces the scene's mesh, which is no longer reloaded when the model
+    /// it came from changes.
+    pub fn set_mesh(&mut self, mesh: Mesh) {
+        self.gpu.update_mesh(&mesh);
+        self.scene.mesh = mesh;
+        self.scene.mesh_label = None;
+    }
+
+    /// Replaces the scene's texture, labelling it `label` in errors. On
+    /// failure the scene is left as it was.
+    pub async fn set_texture(&mut self, data: TextureData, label: &str) -> Result<(), EngineError> {
+        self.gpu.update_texture(&data, label).await?;
+        self.scene.texture = data;
+        self.scene.texture_label = label.to_string();
+        Ok(())
+    }
+
+    /// Sets the matrix that takes the mesh's positions into clip space,
+    /// the view and projection combined. It starts as the identity, which
+    /// draws positions as they are.
+    pub fn set_camera(&mut self, view_proj: Mat4) {
+        self.scene.camera = view_proj;
+        self.gpu.write_camera(view_proj);
+    }
+
+    /// The adapters that were found and the surface settings chosen at
+    /// startup.
+    pub fn capabilities(&self) -> &CapabilityReport {
+        &self.capabilities
+    }
+
+    /// Loads textures, meshes and materials for the app. Anything loaded here
+    /// is uploaded at the start of the next frame, and loaded again if the
+    /// device is lost.
+    pub fn assets(&self) -> &AssetServer {
+        &self.assets
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.gpu.device
+    }
+
+    pub fn queue(&self) -> &Queue {
+        &self.gpu.queue
+    }
+
+    /// The format of the surface, or of the offscreen target if headless.
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    pub fn size(&self) -> PhysicalSize<u32> {
+        PhysicalSize::new(self.width, self.height)
+    }
+
+    /// The samples per pixel the engine draws with. This is `--msaa`, or 1
+    /// if the adapter can't multisample the target at that count.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn window(&self) -> Option<&Arc<Window>> {
+        self.window.as_ref()
+    }
+
+    /// Reconfigures the surface and any multisampled target to the new size.
+    /// Zero-sized requests, as sent when a window is minimized, are ignored.
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        self.width = new_size.width;
+        self.height = new_size.height;
+        if let (Some(surface), Some(config)) = (&self.surface, &mut self.surface_config) {
+            config.width = self.width;
+            config.height = self.height;
+            surface.configure(&self.gpu.device, config);
+        }
+        self.gpu.msaa_view = GpuResources::create_msaa_view(
+            &self.gpu.device,
+            self.format,
+            self.sample_count,
+            new_size,
         );
+        self.gpu.depth_view =
+            GpuResources::create_depth_view(&self.gpu.device, self.sample_count, new_size);
+    }
 
-        let surface_caps = surface.get_capabilities(&adapter);
+    /// Asks the window for a redraw. Does nothing when headless.
+    pub fn request_redraw(&self) {
+        if let Some(window) = &self.window {
+            window.request_redraw();
+        }
+    }
 
-        let format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .context("Surface does not support any sRGB texture formats")
-            .unwrap_or(surface_caps.formats[0]);
+    /// Reconfigures the surface at its current size, which is how a lost or
+    /// outdated surface is recovered.
+    pub fn reconfigure(&mut self) {
+        self.resize(self.size());
+    }
 
-        //TODO: ensure we have a usable present_mode and alpha_mode
-        let config = SurfaceConfiguration {
-            usage: TextureUsages::RENDER_ATTACHMENT,
-            format,
-            width,
-            height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-            desired_maximum_frame_latency: 2,
+    /// Draws a frame to the surface, or offscreen if the engine is headless.
+    pub fn render(&mut self) -> Result<(), EngineError> {
+        let Some(surface) = &self.surface else {
+            return self.render_offscreen().map(|_| ());
+        };
+        self.check_device()?;
+        self.assets.update(&self.gpu.device, &self.gpu.queue);
+        let target = surface.get_current_texture()?;
+        let view = target.texture.create_view(&TextureViewDescriptor {
+            label: Some("Engine::render target texture"),
+            ..Default::default()
+        });
+        // println!("TextureView: {:#?}", view);
+        let mut encoder = self
+            .gpu
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Engine::render CommandEncoder"),
+            });
+        self.draw(&mut encoder, &view);
+        self.gpu.queue.submit(once(encoder.finish()));
+        target.present();
+        Ok(())
+    }
+
+    /// Renders a frame into an offscreen texture the size of the surface,
+    /// which is kept for the next frame and returned.
+    pub fn render_offscreen(&mut self) -> Result<&Texture, EngineError> {
+        self.check_device()?;
+        self.assets.update(&self.gpu.device, &self.gpu.queue);
+        let stale = self.gpu.offscreen.as_ref().is_none_or(|target| {
+            (target.size.width, target.size.height) != (self.width, self.height)
+        });
+        if stale {
+            self.gpu.offscreen = Some(Texture::create_2d_texture(
+                &self.gpu.device,
+                self.width,
+                self.height,
+                self.format,
+                TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+                FilterMode::Nearest,
+                Some("Engine.offscreen"),
+            ));
+        }
+        let target = self
+            .gpu
+            .offscreen
+            .as_ref()
+            .expect("offscreen target was just created");
+        let mut encoder = self
+            .gpu
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Engine::render_offscreen CommandEncoder"),
+            });
+        self.draw(&mut encoder, &target.view);
+        self.gpu.queue.submit(once(encoder.finish()));
+        Ok(target)
+    }
+
+    /// Copies the frame last drawn by [`Engine::render_offscreen`] back to the
+    /// CPU.
+    pub async fn read_offscreen(&self) -> Result<RgbaImage, EngineError> {
+        let target = self.gpu.offscreen.as_ref().ok_or_else(|| {
+            EngineError::Readback("nothing has been rendered offscreen yet".into())
+        })?;
+        target.to_image(&self.gpu.device, &self.gpu.queue).await
+    }
+
+    /// Renders a frame offscreen and copies it back to the CPU.
+    pub async fn render_to_image(&mut self) -> Result<RgbaImage, EngineError> {
+        self.render_offscreen()?;
+        self.read_offscreen().await
+    }
+
+    fn check_device(&self) -> Result<(), EngineError> {
+        if self.is_device_lost() {
+            Err(EngineError::DeviceLost(
+                "call Engine::recover before rendering".into(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Records the render pass that draws the scene into `view`, resolving
+    /// through the multisampled texture if MSAA is enabled. Fragments are
+    /// depth tested with `LessEqual`, as the CPU renderer does.
+    fn draw(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        let gpu = &self.gpu;
+        let (view, resolve_target) = match &gpu.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(view)),
+            None => (view, None),
         };
+        {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Engine::render RenderPass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: Operations {
+                        load: LoadOp::Clear(CLEAR_COLOR),
+                        store: StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &gpu.depth_view,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(1.0),
+                        store: StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&gpu.render_pipeline);
+            render_pass.set_vertex_buffer(0, gpu.vertex_buffer.slice(..));
+            render_pass.set_bind_group(0, &gpu.bind_group, &[]);
+            render_pass.set_index_buffer(gpu.index_buffer.slice(..), IndexFormat::Uint32);
+            render_pass.draw_indexed(0..self.scene.mesh.indices.len() as u32, 0, 0..1);
+        }
+    }
+}
 
-        //TODO: why does unwrap work here, while using a ? causes a compile error using wasm-pack?
-        let (device, queue) = device_fut.await.unwrap();
-        log::debug!("About to configure surface {:?} using config {:?}", surface, config);
-        surface.configure(&device, &config);
+impl fmt::Display for Engine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.window {
+            Some(window) => {
+                let PhysicalSize { width, height } = window.inner_size();
+                write!(
+                    f,
+                    "Engine {{ window: {}x{}, msaa: {}x }}",
+                    width, height, self.sample_count
+                )
+            }
+            None => write!(
+                f,
+                "Engine {{ headless: {}x{}, msaa: {}x }}",
+                self.width, self.height, self.sample_count
+            ),
+        }
+    }
+}
+
+impl Drop for GpuResources {
+    fn drop(&mut self) {
+        self.status.dropped.store(true, Ordering::Release);
+    }
+}
 
-        let texture = load_texture("blue_square_arrows_up_right.png", false, &device, &queue)
-            .await
-            .unwrap();
+impl GpuResources {
+    /// Uploads `scene` to `device`, turning validation errors during texture,
+    /// shader and pipeline creation into [`EngineError`]s rather than letting
+    /// them reach the uncaptured error handler.
+    async fn new(
+        device: Device,
+        queue: Queue,
+        scene: &Scene,
+        format: TextureFormat,
+        sample_count: u32,
+        size: PhysicalSize<u32>,
+    ) -> Result<Self, EngineError> {
+        let status = Arc::new(DeviceStatus::default());
+        Self::watch(&device, &status);
 
         let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("Engine.bind_group_layout"),
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Texture {
-                        sample_type: TextureSampleType::Float { filterable: true },
-                        view_dimension: TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
+            entries: scene.reflection.bind_group(0),
+        });
+        let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Engine.camera_buffer"),
+            contents: cast_slice(&camera_uniform(scene.camera)),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
+        let (texture, bind_group) = Self::create_texture(
+            &device,
+            &queue,
+            &bind_group_layout,
+            &camera_buffer,
+            &scene.texture,
+            &scene.texture_label,
+        )
+        .await?;
+
+        let render_pipeline = Self::create_pipeline(
+            &device,
+            &bind_group_layout,
+            &scene.shader,
+            format,
+            sample_count,
+        )
+        .await?;
 
+        let (vertex_buffer, index_buffer) = Self::create_mesh_buffers(&device, &scene.mesh);
+
+        let msaa_view = Self::create_msaa_view(&device, format, sample_count, size);
+        let depth_view = Self::create_depth_view(&device, sample_count, size);
+
+        Ok(Self {
+            bind_group,
+            bind_group_layout,
+            camera_buffer,
+            depth_view,
+            device,
+            msaa_view,
+            offscreen: None,
+            queue,
+            render_pipeline,
+            status,
+            texture,
+            vertex_buffer,
+            index_buffer,
+        })
+    }
+
+    /// Uploads `data` and creates the bind group that samples it, along with
+    /// the camera, inside an error scope so that a texture the device can't
+    /// hold is reported as an error.
+    async fn create_texture(
+        device: &Device,
+        queue: &Queue,
+        bind_group_layout: &BindGroupLayout,
+        camera_buffer: &Buffer,
+        data: &TextureData,
+        label: &str,
+    ) -> Result<(Texture, BindGroup), EngineError> {
+        device.push_error_scope(ErrorFilter::Validation);
+        let texture = Texture::from_texture_data(
+            device,
+            queue,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+            Some(label),
+        );
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: Some("Engine.bind_group"),
-            layout: &bind_group_layout,
+            layout: bind_group_layout,
             entries: &[
                 BindGroupEntry {
-                    binding: 0,
+                    binding: bindings::TEX_DIFFUSE_BINDING,
                     resource: BindingResource::TextureView(&texture.view),
                 },
                 BindGroupEntry {
-                    binding: 1,
+                    binding: bindings::SMP_DIFFUSE_BINDING,
                     resource: BindingResource::Sampler(&texture.sampler),
                 },
+                BindGroupEntry {
+                    binding: bindings::CAMERA_BINDING,
+                    resource: camera_buffer.as_entire_binding(),
+                },
             ],
         });
+        if let Some(e) = device.pop_error_scope().await {
+            return Err(EngineError::Validation {
+                label: label.to_string(),
+                message: e.to_string(),
+            });
+        }
+        Ok((texture, bind_group))
+    }
+
+    fn create_mesh_buffers(device: &Device, mesh: &Mesh) -> (Buffer, Buffer) {
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Engine.vertex_buffer"),
+            contents: cast_slice(&mesh.vertices),
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        });
+        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Engine.index_buffer"),
+            contents: cast_slice(&mesh.indices),
+            usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
+        });
+        (vertex_buffer, index_buffer)
+    }
+
+    /// Writes `data` into the scene's texture, or replaces the texture and
+    /// its bind group if the size changed.
+    async fn update_texture(&mut self, data: &TextureData, label: &str) -> Result<(), EngineError> {
+        if !self.texture.write_texture_data(&self.queue, data) {
+            (self.texture, self.bind_group) = Self::create_texture(
+                &self.device,
+                &self.queue,
+                &self.bind_group_layout,
+                &self.camera_buffer,
+                data,
+                label,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    fn write_camera(&self, view_proj: Mat4) {
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            cast_slice(&camera_uniform(view_proj)),
+        );
+    }
 
-        let module = device.create_shader_module(include_wgsl!("shader.wgsl"));
+    /// Writes `mesh` into the scene's buffers, or replaces them if the size
+    /// changed.
+    fn update_mesh(&mut self, mesh: &Mesh) {
+        if !mesh.write_buffers(&self.queue, &self.vertex_buffer, &self.index_buffer) {
+            (self.vertex_buffer, self.index_buffer) = Self::create_mesh_buffers(&self.device, mesh);
+        }
+    }
 
+    /// Compiles `shader` and builds the render pipeline from it. Both steps
+    /// run inside error scopes, so a bad shader is reported as an error
+    /// instead of invalidating the device.
+    async fn create_pipeline(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        shader: &ShaderSource,
+        format: TextureFormat,
+        sample_count: u32,
+    ) -> Result<RenderPipeline, EngineError> {
+        device.push_error_scope(ErrorFilter::Validation);
+        let module = device.create_shader_module(shader.descriptor());
+        if let Some(e) = device.pop_error_scope().await {
+            return Err(EngineError::ShaderCompile {
+                label: shader.label.clone(),
+                message: e.to_string(),
+            });
+        }
+
+        device.push_error_scope(ErrorFilter::Validation);
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Engine::new pipeline_layout"),
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[bind_group_layout],
             push_constant_ranges: &[],
         });
 
@@ -199,7 +963,7 @@ impl Engine<'_> {
             layout: Some(&pipeline_layout),
             vertex: VertexState {
                 module: &module,
-                entry_point: "vs_main",
+                entry_point: shader::VERTEX_ENTRY,
                 buffers: &[ModelVertex::desc()],
             },
             primitive: PrimitiveState {
@@ -211,17 +975,23 @@ impl Engine<'_> {
                 polygon_mode: PolygonMode::Fill,
                 conservative: false,
             },
-            depth_stencil: None,
+            depth_stencil: Some(DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::LessEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
             multisample: MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             fragment: Some(FragmentState {
                 module: &module,
-                entry_point: "fs_main",
+                entry_point: shader::FRAGMENT_ENTRY,
                 targets: &[Some(ColorTargetState {
-                    format: config.format,
+                    format,
                     blend: Some(BlendState {
                         alpha: BlendComponent::REPLACE,
                         color: BlendComponent::REPLACE,
@@ -231,74 +1001,77 @@ impl Engine<'_> {
             }),
             multiview: None,
         });
+        match device.pop_error_scope().await {
+            Some(e) => Err(EngineError::Validation {
+                label: "Engine.render_pipeline".into(),
+                message: e.to_string(),
+            }),
+            None => Ok(render_pipeline),
+        }
+    }
 
-        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Engine.vertex_buffer"),
-            contents: cast_slice(SQUARE_VERTICES),
-            usage: BufferUsages::VERTEX,
+    /// Registers callbacks that record uncaptured errors and device loss in
+    /// `status`.
+    fn watch(device: &Device, status: &Arc<DeviceStatus>) {
+        let errors = status.clone();
+        device.on_uncaptured_error(Box::new(move |e| {
+            log::error!("Uncaptured wgpu error: {}", e);
+            errors.errors.lock().unwrap().push(e.to_string());
+        }));
+
+        let lost = status.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            // The callback also fires when the device is dropped or the
+            // callback replaced, neither of which means anything went wrong.
+            // wgpu reports dropping as Unknown, so that is tracked separately.
+            if matches!(
+                reason,
+                DeviceLostReason::Unknown | DeviceLostReason::Destroyed
+            ) && !lost.dropped.load(Ordering::Acquire)
+            {
+                log::error!("Device lost ({:?}): {}", reason, message);
+                lost.lost.store(true, Ordering::Release);
+            }
         });
+    }
 
-        let r = Self {
-            bind_group,
+    fn create_depth_view(
+        device: &Device,
+        sample_count: u32,
+        size: PhysicalSize<u32>,
+    ) -> TextureView {
+        Texture::create_multisampled_texture(
             device,
-            queue,
-            render_pipeline,
-            surface,
-            vertex_buffer,
-            window: window.clone(),
-        };
-
-        println!("Initialized {}", r);
-
-        Ok(r)
+            size.width,
+            size.height,
+            Texture::DEPTH_FORMAT,
+            sample_count,
+            Some("Engine.depth_texture"),
+        )
+        .view
     }
 
-    pub fn render(&self) -> Result<()> {
-        let target = self.surface.get_current_texture()?;
-        let view = target.texture.create_view(&TextureViewDescriptor {
-            label: Some("Engine::render target texture"),
-            ..Default::default()
-        });
-        // println!("TextureView: {:#?}", view);
-        let mut encoder = self
-            .device
-            .create_command_encoder(&CommandEncoderDescriptor {
-                label: Some("Engine::render CommandEncoder"),
-            });
-        {
-            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("Engine::render RenderPass"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_bind_group(0, &self.bind_group, &[]);
-            render_pass.draw(0..6, 0..1);
-        }
-        self.queue.submit(once(encoder.finish()));
-        target.present();
-        Ok(())
+    fn create_msaa_view(
+        device: &Device,
+        format: TextureFormat,
+        sample_count: u32,
+        size: PhysicalSize<u32>,
+    ) -> Option<TextureView> {
+        (sample_count > 1).then(|| {
+            Texture::create_multisampled_texture(
+                device,
+                size.width,
+                size.height,
+                format,
+                sample_count,
+                Some("Engine.msaa_texture"),
+            )
+            .view
+        })
     }
 }
 
-impl fmt::Display for Engine<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let PhysicalSize { width, height } = self.window.inner_size();
-        write!(f, "Engine {{ window: {}x{} }}", width, height)
-    }
+/// The contents of the shader's `camera` uniform.
+fn camera_uniform(view_proj: Mat4) -> [[f32; 4]; 4] {
+    view_proj.cols.map(Vec4::to_array)
 }
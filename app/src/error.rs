@@ -0,0 +1,98 @@
+use std::{error::Error, fmt};
+
+use thiserror::Error;
+use wgpu::{CreateSurfaceError, RequestDeviceError, SurfaceError};
+
+type BoxedError = Box<dyn Error + Send + Sync>;
+
+/// Everything that can go wrong while creating an [`Engine`](crate::engine::Engine)
+/// or loading the resources it draws.
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("No suitable adapter: {0}")]
+    NoAdapter(String),
+    #[error("Failed to request a device")]
+    DeviceRequest(#[from] RequestDeviceError),
+    #[error("Failed to configure the surface: {reason}")]
+    SurfaceConfig {
+        reason: String,
+        #[source]
+        source: Option<BoxedError>,
+    },
+    #[error("Could not find or read asset {path}")]
+    AssetNotFound {
+        path: String,
+        #[source]
+        source: BoxedError,
+    },
+    #[error("Failed to decode asset {path}")]
+    DecodeFailed {
+        path: String,
+        #[source]
+        source: BoxedError,
+    },
+    #[error("Failed to compile shader {label}: {message}")]
+    ShaderCompile { label: String, message: String },
+    #[error("Validation failed for {label}: {message}")]
+    Validation { label: String, message: String },
+    #[error("The device was lost: {0}")]
+    DeviceLost(String),
+    #[error("Failed to acquire the next surface texture")]
+    Surface(#[from] SurfaceError),
+    #[error("Failed to read back texture: {0}")]
+    Readback(String),
+    #[error("Failed to create or run the window")]
+    Window(#[source] BoxedError),
+    #[error("Failed to watch for changes")]
+    Watch(#[source] BoxedError),
+    #[error("Failed to write {path}")]
+    Write {
+        path: String,
+        #[source]
+        source: BoxedError,
+    },
+    /// Anything else an [`App`](crate::app::App) hook fails with.
+    #[error("The app failed")]
+    App(#[source] BoxedError),
+}
+
+impl EngineError {
+    pub(crate) fn asset_not_found(path: &str, source: impl Into<BoxedError>) -> Self {
+        Self::AssetNotFound {
+            path: path.to_string(),
+            source: source.into(),
+        }
+    }
+
+    pub(crate) fn decode_failed(path: &str, source: impl Into<BoxedError>) -> Self {
+        Self::DecodeFailed {
+            path: path.to_string(),
+            source: source.into(),
+        }
+    }
+}
+
+/// Displays an error followed by each of its sources, separated by colons,
+/// for logging the whole story on one line.
+pub struct Chain<'e>(pub &'e dyn Error);
+
+impl fmt::Display for Chain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(error) = source {
+            write!(f, ": {}", error)?;
+            source = error.source();
+        }
+        Ok(())
+    }
+}
+
+impl From<CreateSurfaceError> for EngineError {
+    fn from(value: CreateSurfaceError) -> Self {
+        Self::SurfaceConfig {
+            reason: "the surface could not be created".to_string(),
+            source: Some(value.into()),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use web_time::Instant;
+
+/// The most wall-clock time a single frame is allowed to feed into the
+/// simulation. Anything beyond this, such as time spent paused in a debugger
+/// or with the tab in the background, is dropped rather than simulated.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+/// Tracks wall-clock time between frames and divides it into fixed-size
+/// simulation steps, as described in "Fix Your Timestep!" by Glenn Fiedler.
+/// Time that doesn't add up to a whole step carries over to the next frame,
+/// and the fraction of a step it represents is exposed as [`FrameClock::alpha`]
+/// so rendering can interpolate between the last two simulated states.
+#[derive(Debug)]
+pub struct FrameClock {
+    step: Duration,
+    min_frame_time: Option<Duration>,
+    accumulator: Duration,
+    last_tick: Instant,
+    last_frame: Option<Instant>,
+}
+
+impl FrameClock {
+    /// Creates a clock that steps the simulation `update_rate` times per
+    /// second, and, if `max_fps` is given, spaces frames at least `1 / max_fps`
+    /// seconds apart.
+    ///
+    /// Both rates must be finite, positive and at most 1e9 per second, as
+    /// [`Config`](crate::config::Config) checks when it parses them.
+    pub fn new(update_rate: f64, max_fps: Option<f64>) -> Self {
+        Self {
+            step: Duration::from_secs_f64(1.0 / update_rate),
+            min_frame_time: max_fps.map(|fps| Duration::from_secs_f64(1.0 / fps)),
+            accumulator: Duration::ZERO,
+            last_tick: Instant::now(),
+            last_frame: None,
+        }
+    }
+
+    /// The length of one simulation step.
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// When the next frame may start, if a frame cap is in effect.
+    pub fn next_frame(&self) -> Option<Instant> {
+        Some(self.last_frame? + self.min_frame_time?)
+    }
+
+    /// Adds the time elapsed since the previous tick and returns how many
+    /// whole simulation steps are now due.
+    pub fn tick(&mut self, now: Instant) -> u32 {
+        let elapsed = now.saturating_duration_since(self.last_tick);
+        self.last_tick = now;
+        self.accumulator += elapsed.min(MAX_FRAME_TIME);
+
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// When the next simulation step falls due, counting from the last tick.
+    pub fn next_step(&self) -> Instant {
+        self.last_tick + (self.step - self.accumulator)
+    }
+
+    /// How far, from 0 to 1, the clock is between the last simulation step and
+    /// the next one.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+
+    /// Records that a frame was presented at `now`, for the frame cap.
+    pub fn finish_frame(&mut self, now: Instant) {
+        self.last_frame = Some(now);
+    }
+}
@@ -0,0 +1,16 @@
+use crate::{app::App, engine::Engine, error::EngineError};
+
+/// The textured square from the introductory post, drawn entirely by the
+/// engine's default pipeline.
+#[derive(Debug, Default)]
+pub struct Intro;
+
+impl App for Intro {
+    fn render(&mut self, engine: &mut Engine, _alpha: f32) -> Result<(), EngineError> {
+        engine.render()
+    }
+
+    fn draws_config_scene(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,3 @@
+mod intro;
+
+pub use intro::Intro;
@@ -1,33 +1,165 @@
-use std::{process::Termination, sync::Arc};
+use std::sync::Arc;
 
+use app::App;
 use cfg_if::cfg_if;
+use config::Config;
 use engine::Engine;
+use error::EngineError;
+use frame::FrameClock;
+use web_time::Instant;
+use wgpu::SurfaceError;
 use winit::{
     dpi::LogicalSize,
     event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
+    event_loop::{ControlFlow, EventLoopBuilder},
     window::WindowBuilder,
 };
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-mod engine;
-mod resources;
-mod texture;
+pub mod app;
+pub mod assets;
+pub mod bake;
+pub mod capabilities;
+pub mod compare;
+pub mod config;
+pub mod cpu;
+pub mod engine;
+pub mod error;
+pub mod frame;
+pub mod lessons;
+pub mod pack;
+mod preprocess;
+pub mod reflect;
+pub mod renderer;
+pub mod resources;
+pub mod shader;
+pub mod source;
+pub mod texture;
+pub mod tga;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod watch;
 
-#[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
-pub async fn run() {
+/// Events sent to the event loop from other threads.
+#[derive(Debug)]
+enum RunnerEvent {
+    #[cfg(not(target_arch = "wasm32"))]
+    FileChanged(std::path::PathBuf),
+    /// An asset that was read by a [`watch::WatchedSource`] changed.
+    #[cfg(not(target_arch = "wasm32"))]
+    AssetChanged(String),
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(start)]
+pub async fn start() {
+    if let Err(e) = run(Config::default()).await {
+        log::error!("{}", error::Chain(&e));
+    }
+}
+
+/// Runs the introductory lesson.
+pub async fn run(config: Config) -> Result<(), EngineError> {
+    run_app(config, lessons::Intro).await
+}
+
+/// Creates the window and engine described by `config`, then drives `app`
+/// from the event loop until the window is closed.
+pub async fn run_app<A: App + 'static>(config: Config, mut app: A) -> Result<(), EngineError> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-            console_log::init_with_level(log::Level::Debug);
+            let level = config
+                .log_level
+                .and_then(|l| l.to_level())
+                .unwrap_or(log::Level::Debug);
+            console_log::init_with_level(level).ok();
         } else {
-            env_logger::init()
+            let mut builder = env_logger::Builder::from_default_env();
+            if let Some(level) = config.log_level {
+                builder.filter_level(level);
+            }
+            builder.init();
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let (Some(dir), false) = (&config.asset_dir, config.watch_assets) {
+        source::set_asset_source(source::FileSource::new(dir));
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = &config.asset_pack {
+        let reader = source::MappedPack::open(path)?;
+        source::set_asset_source(source::PackSource::new(reader));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if config.list_adapters {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: config.backend.into(),
+            ..Default::default()
+        });
+        for (i, adapter) in instance
+            .enumerate_adapters(config.backend.into())
+            .iter()
+            .enumerate()
+        {
+            println!("[{}] {}", i, capabilities::AdapterReport::new(adapter));
         }
+        return Ok(());
     }
-    let event_loop = EventLoop::new().unwrap();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = &config.headless {
+        let image = if config.cpu {
+            if !app.draws_config_scene() {
+                return Err(EngineError::App(
+                    "--cpu can't draw an app that customises rendering".into(),
+                ));
+            }
+            let scene = renderer::Scene::from_config(&config).await?;
+            scene
+                .render(&mut cpu::CpuRenderer::from_config(&config))
+                .await?
+        } else {
+            let mut engine = Engine::new_headless(&config).await?;
+            app.init(&mut engine)?;
+            app.update(
+                &mut engine,
+                FrameClock::new(config.update_rate, None).step(),
+            );
+            app.render(&mut engine, 0.0)?;
+            engine.read_offscreen().await?
+        };
+        tga::save(&image.into(), path).map_err(|e| EngineError::Write {
+            path: path.display().to_string(),
+            source: e.into(),
+        })?;
+        log::info!("Wrote {}", path.display());
+        return Ok(());
+    }
+
+    let event_loop = EventLoopBuilder::<RunnerEvent>::with_user_event()
+        .build()
+        .map_err(|e| EngineError::Window(e.into()))?;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if config.watch_assets {
+        let dir = match &config.asset_dir {
+            Some(dir) => dir.clone(),
+            None => std::path::PathBuf::from(shader::SOURCE_DIR),
+        };
+        let proxy = event_loop.create_proxy();
+        let source = watch::WatchedSource::new(&dir, move |path| {
+            proxy.send_event(RunnerEvent::AssetChanged(path)).ok();
+        })
+        .map_err(|e| EngineError::Watch(e.into()))?;
+        source::set_asset_source(source);
+    }
+    let window = WindowBuilder::new()
+        .build(&event_loop)
+        .map_err(|e| EngineError::Window(e.into()))?;
     let window = Arc::new(window);
 
     #[cfg(target_arch = "wasm32")]
@@ -44,36 +176,179 @@ pub async fn run() {
             .expect("Couldn't append canvas to document body.");
     }
 
-    if let Some(new_size) = window.request_inner_size(LogicalSize::new(400, 400)) {
+    if let Some(new_size) =
+        window.request_inner_size(LogicalSize::new(config.window_width, config.window_height))
+    {
         log::debug!("Got new size after request: {:?}", new_size);
     } else {
         log::debug!("None returned from request");
     }
 
-    let engine = Engine::new(window, 800, 800).await.unwrap();
-
-    event_loop.set_control_flow(ControlFlow::Poll);
-
-    event_loop
-        .run(move |event, target| match event {
-            //TODO: check window_id
-            #[cfg(not(target_arch = "wasm32"))]
-            Event::WindowEvent {
-                event: WindowEvent::CloseRequested,
-                ..
-            } => target.exit(),
-            Event::WindowEvent {
-                event: WindowEvent::Resized(new_size),
-                ..
-            } => {
-                log::debug!("Window was just resized to {:?}", new_size);
+    let mut engine = Engine::new(window, &config).await?;
+
+    // The watcher stops when dropped, so it has to outlive the event loop
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut watcher = if config.watch_shaders {
+        let proxy = event_loop.create_proxy();
+        let mut watcher = watch::FileWatcher::new(move |path| {
+            proxy.send_event(RunnerEvent::FileChanged(path)).ok();
+        })
+        .map_err(|e| EngineError::Watch(e.into()))?;
+        reload_shader(&mut engine, &mut watcher);
+        Some(watcher)
+    } else {
+        None
+    };
+
+    app.init(&mut engine)?;
+    let mut clock = FrameClock::new(config.update_rate, config.max_fps);
+    let on_demand = config.on_demand;
+    // Set by changes the app can't see for itself, or input it consumed, and
+    // used in on-demand mode to decide whether the next turn of the loop
+    // should redraw. Everything else redraws through Engine::request_redraw.
+    let mut dirty = true;
+
+    event_loop.set_control_flow(if on_demand {
+        ControlFlow::Wait
+    } else {
+        ControlFlow::Poll
+    });
+
+    let result = event_loop.run(move |event, target| match event {
+        //TODO: check window_id
+        Event::WindowEvent {
+            event: WindowEvent::RedrawRequested,
+            ..
+        } => {
+            if engine.is_device_lost() {
+                recover(&mut engine, &mut app);
+            }
+            let now = Instant::now();
+            // On demand, the updates run in AboutToWait whether or not
+            // anything is drawn
+            if !on_demand {
+                for _ in 0..clock.tick(now) {
+                    app.update(&mut engine, clock.step());
+                }
             }
-            Event::AboutToWait => {
-                if let Err(e) = engine.render() {
-                    log::error!("got some kind of error while rendering: {}", e);
+            if let Err(e) = app.render(&mut engine, clock.alpha()) {
+                match e {
+                    EngineError::Surface(SurfaceError::Lost | SurfaceError::Outdated) => {
+                        engine.reconfigure()
+                    }
+                    EngineError::DeviceLost(_) => recover(&mut engine, &mut app),
+                    e => log::error!(
+                        "got some kind of error while rendering: {}",
+                        error::Chain(&e)
+                    ),
                 }
             }
-            _ => {}
+            clock.finish_frame(now);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        Event::UserEvent(RunnerEvent::FileChanged(path)) => {
+            log::debug!("{} changed", path.display());
+            if let Some(watcher) = &mut watcher {
+                reload_shader(&mut engine, watcher);
+            }
+            dirty = true;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        Event::UserEvent(RunnerEvent::AssetChanged(path)) => {
+            log::info!("Reloading {}", path);
+            if let Err(e) = pollster::block_on(engine.reload_asset(&path)) {
+                log::error!("Failed to reload {}: {}", path, error::Chain(&e));
+            }
+            dirty = true;
+        }
+        Event::WindowEvent { event, .. } if app.input(&mut engine, &event) => dirty = true,
+        #[cfg(not(target_arch = "wasm32"))]
+        Event::WindowEvent {
+            event: WindowEvent::CloseRequested,
+            ..
+        } => target.exit(),
+        Event::WindowEvent {
+            event: WindowEvent::Resized(new_size),
+            ..
+        } => {
+            log::debug!("Window was just resized to {:?}", new_size);
+            engine.resize(new_size);
+            let size = engine.size();
+            app.resize(&mut engine, size);
+            dirty = true;
+        }
+        Event::WindowEvent {
+            event: WindowEvent::ScaleFactorChanged { .. } | WindowEvent::Occluded(_),
+            ..
+        } => dirty = true,
+        Event::AboutToWait if on_demand => {
+            for _ in 0..clock.tick(Instant::now()) {
+                app.update(&mut engine, clock.step());
+            }
+            if dirty {
+                engine.request_redraw();
+                dirty = false;
+            }
+            target.set_control_flow(ControlFlow::WaitUntil(clock.next_step()));
+        }
+        Event::AboutToWait => match clock.next_frame() {
+            Some(deadline) if Instant::now() < deadline => {
+                target.set_control_flow(ControlFlow::WaitUntil(deadline));
+            }
+            _ => {
+                target.set_control_flow(ControlFlow::Poll);
+                engine.request_redraw();
+            }
+        },
+        _ => {}
+    });
+    result.map_err(|e| EngineError::Window(e.into()))
+}
+
+/// Rebuilds the engine's GPU resources after the device is lost, then gives
+/// the app a chance to rebuild its own.
+fn recover<A: App>(engine: &mut Engine, app: &mut A) {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            // Requesting a device is asynchronous in the browser, and the
+            // event loop can't wait for it, so leave recovery to a page reload
+            let _ = (engine, app);
+            log::error!("The GPU device was lost; reload the page to recover");
+        } else {
+            let result = pollster::block_on(engine.recover()).and_then(|()| app.init(engine));
+            match result {
+                Ok(()) => log::info!("Recovered from device loss"),
+                Err(e) => log::error!("Failed to recover from device loss: {}", error::Chain(&e)),
+            }
+        }
+    }
+}
+
+/// Preprocesses the engine's shader from the source tree and loads it into
+/// the engine, watching every file it was assembled from. Errors are logged,
+/// and leave the previous pipeline in place.
+#[cfg(not(target_arch = "wasm32"))]
+fn reload_shader(engine: &mut Engine, watcher: &mut watch::FileWatcher) {
+    let dir = std::path::Path::new(shader::SOURCE_DIR);
+    let result = shader::Preprocessor::new()
+        .process(shader::ENTRY, |path| {
+            std::fs::read_to_string(dir.join(path))
+                .map(std::borrow::Cow::Owned)
+                .map_err(|e| EngineError::asset_not_found(path, e))
         })
-        .report();
+        .and_then(|source| {
+            for file in &source.files {
+                if let Err(e) = watcher.watch(&dir.join(file)) {
+                    log::warn!("Failed to watch {}: {}", file, e);
+                }
+            }
+            pollster::block_on(engine.reload_shader(source))
+        });
+    match result {
+        Ok(()) => log::info!("Loaded shader {}", shader::ENTRY),
+        Err(EngineError::ShaderCompile { message, .. }) => {
+            log::error!("Keeping the previous pipeline:\n{}", message)
+        }
+        Err(e) => log::error!("Keeping the previous pipeline: {}", e),
+    }
 }
@@ -1,5 +1,8 @@
-use tinyrenderer_wgpu::run;
+use anyhow::Result;
+use clap::Parser;
+use tinyrenderer_wgpu::{config::Config, run};
 
-fn main() {
-    pollster::block_on(run());
+fn main() -> Result<()> {
+    pollster::block_on(run(Config::parse()))?;
+    Ok(())
 }
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use crate::bake::FormatError;
+
+/// Bumped whenever the layout below changes.
+pub const VERSION: u32 = 1;
+
+/// The name the build script gives the pack of every asset.
+pub const FILE_NAME: &str = "assets.pack";
+
+const MAGIC: &[u8; 4] = b"TRPK";
+
+/// The size of the header, which holds the magic number, the version and the
+/// size of the index that follows it.
+pub const HEADER_LEN: u64 = 12;
+
+/// Where each file is within a pack.
+///
+/// A pack is the header, then the index, then the contents of every file
+/// back to back. Readers fetch the header and index first, then only the
+/// ranges they need, so a pack can be read over HTTP without downloading all
+/// of it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Index {
+    /// The offset from the start of the pack and the length of each file.
+    entries: BTreeMap<String, (u64, u64)>,
+}
+
+impl Index {
+    /// Reads the size of the index from the start of a pack.
+    pub fn len_from_header(header: &[u8]) -> Result<u64, FormatError> {
+        if header.len() < HEADER_LEN as usize || &header[..4] != MAGIC {
+            return Err(FormatError("not an asset pack".into()));
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(FormatError(format!(
+                "packed with version {}, expected {}",
+                version, VERSION
+            )));
+        }
+        Ok(u32::from_le_bytes(header[8..12].try_into().unwrap()).into())
+    }
+
+    /// Parses the index, which starts right after the header.
+    pub fn decode(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut entries = BTreeMap::new();
+        let mut rest = bytes;
+        let mut take = |len: usize| {
+            if rest.len() < len {
+                return Err(FormatError("unexpected end of index".into()));
+            }
+            let (head, tail) = rest.split_at(len);
+            rest = tail;
+            Ok(head)
+        };
+        let count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        for _ in 0..count {
+            let name_len = u32::from_le_bytes(take(4)?.try_into().unwrap());
+            let name = std::str::from_utf8(take(name_len as usize)?)
+                .map_err(|e| FormatError(e.to_string()))?
+                .to_string();
+            let offset = u64::from_le_bytes(take(8)?.try_into().unwrap());
+            let len = u64::from_le_bytes(take(8)?.try_into().unwrap());
+            entries.insert(name, (offset, len));
+        }
+        Ok(Self { entries })
+    }
+
+    /// The offset and length of the file at `path`.
+    pub fn get(&self, path: &str) -> Option<(u64, u64)> {
+        self.entries.get(path).copied()
+    }
+}
+
+/// Packs `files`, given as asset paths and contents.
+pub fn write<'f>(files: impl IntoIterator<Item = (&'f str, &'f [u8])>) -> Vec<u8> {
+    let files: Vec<_> = files.into_iter().collect();
+    let index_len = 4 + files
+        .iter()
+        .map(|(name, _)| 4 + name.len() + 16)
+        .sum::<usize>();
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&(index_len as u32).to_le_bytes());
+    out.extend_from_slice(&(files.len() as u32).to_le_bytes());
+    let mut offset = HEADER_LEN + index_len as u64;
+    for (name, data) in &files {
+        out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        offset += data.len() as u64;
+    }
+    for (_, data) in &files {
+        out.extend_from_slice(data);
+    }
+    out
+}
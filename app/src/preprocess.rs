@@ -0,0 +1,242 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    fmt::Write,
+    ops::Range,
+};
+
+/// The result of preprocessing a file.
+pub struct Output {
+    pub code: String,
+    /// The paths of every file that was included, starting with the entry
+    /// point.
+    pub files: Vec<String>,
+    /// For each line of `code`, the index in `files` of the file it came
+    /// from and its line number there, counting from 1.
+    pub lines: Vec<(usize, usize)>,
+}
+
+/// Expands `entry` starting from `defines`, calling `read` to get the contents
+/// of it and of each file it includes. Mistakes in the directives themselves
+/// are turned into errors by `syntax_error`.
+///
+/// This module has no dependencies on the rest of the crate, so the build
+/// script can use it to validate the shaders.
+pub fn expand<'a, E>(
+    entry: &str,
+    defines: &BTreeMap<String, String>,
+    mut read: impl FnMut(&str) -> Result<Cow<'a, str>, E>,
+    syntax_error: impl Fn(String) -> E,
+) -> Result<Output, E> {
+    let mut state = State {
+        defines: defines.clone(),
+        files: Vec::new(),
+        included: HashSet::new(),
+        output: String::new(),
+        lines: Vec::new(),
+        syntax_error: &syntax_error,
+    };
+    state.expand(entry, &mut read)?;
+    Ok(Output {
+        code: state.output,
+        files: state.files,
+        lines: state.lines,
+    })
+}
+
+/// Describes where in the original files each of `labels`, byte ranges of
+/// expanded `code` and what a diagnostic says about them, came from, one
+/// `= at file:line:column: label` line each, to follow a diagnostic about
+/// the expanded code. Columns are counted in the expanded line, so they're
+/// off where a define was substituted before them.
+pub fn locate<'l>(
+    code: &str,
+    files: &[String],
+    lines: &[(usize, usize)],
+    labels: impl IntoIterator<Item = (Range<usize>, &'l str)>,
+) -> String {
+    let mut out = String::new();
+    for (range, label) in labels {
+        let Some(prefix) = code.get(..range.start) else {
+            continue;
+        };
+        let line = prefix.matches('\n').count();
+        let column = prefix[prefix.rfind('\n').map_or(0, |i| i + 1)..]
+            .chars()
+            .count()
+            + 1;
+        if let Some(&(file, line)) = lines.get(line) {
+            let _ = writeln!(out, "  = at {}:{}:{}: {}", files[file], line, column, label);
+        }
+    }
+    out
+}
+
+/// The paths of the files `code`, which lives at `path`, includes, whether or
+/// not the `#include`s are inside live branches.
+pub fn includes<'c>(path: &'c str, code: &'c str) -> impl Iterator<Item = String> + 'c {
+    code.lines()
+        .filter_map(move |line| match Directive::parse(line) {
+            Some(Directive::Include(name)) => Some(resolve(path, name)),
+            _ => None,
+        })
+}
+
+pub enum Directive<'a> {
+    Include(&'a str),
+    Define(&'a str, &'a str),
+    Undef(&'a str),
+    Ifdef(&'a str),
+    Ifndef(&'a str),
+    Else,
+    Endif,
+    Unknown(&'a str),
+}
+
+impl<'a> Directive<'a> {
+    /// Returns `None` if `line` isn't a directive at all.
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let rest = line.trim().strip_prefix('#')?;
+        let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let args = args.trim();
+        Some(match name {
+            "include" => Directive::Include(args.trim_matches('"')),
+            "define" => {
+                let (name, value) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                Directive::Define(name, value.trim())
+            }
+            "undef" => Directive::Undef(args),
+            "ifdef" => Directive::Ifdef(args),
+            "ifndef" => Directive::Ifndef(args),
+            "else" => Directive::Else,
+            "endif" => Directive::Endif,
+            _ => Directive::Unknown(name),
+        })
+    }
+}
+
+struct State<'s, F> {
+    defines: BTreeMap<String, String>,
+    files: Vec<String>,
+    included: HashSet<String>,
+    output: String,
+    lines: Vec<(usize, usize)>,
+    syntax_error: &'s F,
+}
+
+impl<F> State<'_, F> {
+    fn expand<'a, E>(
+        &mut self,
+        path: &str,
+        read: &mut impl FnMut(&str) -> Result<Cow<'a, str>, E>,
+    ) -> Result<(), E>
+    where
+        F: Fn(String) -> E,
+    {
+        if !self.included.insert(path.to_string()) {
+            return Ok(());
+        }
+        let file = self.files.len();
+        self.files.push(path.to_string());
+        let code = read(path)?;
+
+        // One entry per open #ifdef: whether its current branch is live
+        let mut conditions: Vec<bool> = Vec::new();
+        for (i, line) in code.lines().enumerate() {
+            let error =
+                |message: &str| (self.syntax_error)(format!("{}:{}: {}", path, i + 1, message));
+            let live = conditions.iter().all(|c| *c);
+            // Directives and dead lines are left blank, so the lines of each
+            // file stay together where they can be
+            let Some(directive) = Directive::parse(line) else {
+                if live {
+                    let line = substitute(line, &self.defines);
+                    self.push_line(file, i, &line);
+                } else {
+                    self.push_line(file, i, "");
+                }
+                continue;
+            };
+            self.push_line(file, i, "");
+            match directive {
+                Directive::Ifdef(name) => conditions.push(self.defines.contains_key(name)),
+                Directive::Ifndef(name) => conditions.push(!self.defines.contains_key(name)),
+                Directive::Else => match conditions.last_mut() {
+                    Some(condition) => *condition = !*condition,
+                    None => return Err(error("#else without #ifdef")),
+                },
+                Directive::Endif => {
+                    if conditions.pop().is_none() {
+                        return Err(error("#endif without #ifdef"));
+                    }
+                }
+                _ if !live => {}
+                Directive::Include(name) => self.expand(&resolve(path, name), read)?,
+                Directive::Define(name, value) => {
+                    self.defines.insert(name.to_string(), value.to_string());
+                }
+                Directive::Undef(name) => {
+                    self.defines.remove(name);
+                }
+                Directive::Unknown(name) => {
+                    return Err(error(&format!("unknown directive #{}", name)))
+                }
+            }
+        }
+        if !conditions.is_empty() {
+            return Err((self.syntax_error)(format!("{}: missing #endif", path)));
+        }
+        Ok(())
+    }
+
+    /// Appends line `i`, counting from 0, of file `file`.
+    fn push_line(&mut self, file: usize, i: usize, line: &str) {
+        self.output.push_str(line);
+        self.output.push('\n');
+        self.lines.push((file, i + 1));
+    }
+}
+
+/// Resolves `name`, as written in an `#include` in the file at `from`, to an
+/// asset path.
+pub fn resolve(from: &str, name: &str) -> String {
+    let mut parts: Vec<&str> = from.split('/').collect();
+    parts.pop();
+    for part in name.split('/') {
+        match part {
+            "." | "" => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Replaces each whole identifier in `line` that has been defined with its
+/// value.
+fn substitute<'l>(line: &'l str, defines: &BTreeMap<String, String>) -> Cow<'l, str> {
+    if defines.is_empty() {
+        return Cow::Borrowed(line);
+    }
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(|c: char| c.is_alphanumeric() || c == '_') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        // Numbers such as 1u are never names
+        let value = match word.starts_with(|c: char| c.is_ascii_digit()) {
+            true => None,
+            false => defines.get(word),
+        };
+        out.push_str(value.map_or(word, String::as_str));
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
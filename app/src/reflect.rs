@@ -0,0 +1,547 @@
+use std::{collections::BTreeMap, fmt, num::NonZeroU64};
+
+use naga::{
+    valid::ModuleInfo, AddressSpace, ArraySize, Binding, Handle, ImageClass, ImageDimension,
+    Module, ScalarKind, ShaderStage, StorageAccess, StorageFormat, Type, TypeInner, VectorSize,
+};
+use wgpu::{
+    BindGroupLayoutEntry, BindingType, BufferBindingType, SamplerBindingType, ShaderStages,
+    StorageTextureAccess, TextureFormat, TextureSampleType, TextureViewDimension,
+    VertexBufferLayout, VertexFormat,
+};
+
+use crate::error::EngineError;
+
+/// One input of a vertex entry point, as declared in WGSL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexInput {
+    pub name: String,
+    pub location: u32,
+    pub format: VertexFormat,
+}
+
+/// What a shader's entry points expect to be bound, found by walking the
+/// naga module rather than written out by hand.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShaderReflection {
+    /// Layout entries for each bind group, sorted by binding. Each entry is
+    /// visible to exactly the stages whose entry points use it.
+    pub bind_groups: BTreeMap<u32, Vec<BindGroupLayoutEntry>>,
+    /// The vertex entry point's inputs, sorted by location.
+    pub vertex_inputs: Vec<VertexInput>,
+}
+
+impl ShaderReflection {
+    /// Reflects the entry points named in `entry_points`. `info` must come
+    /// from validating `module`.
+    pub fn new(module: &Module, info: &ModuleInfo, entry_points: &[&str]) -> Result<Self, String> {
+        let mut reflection = Self::default();
+        let mut bindings = BTreeMap::<(u32, u32), BindGroupLayoutEntry>::new();
+
+        for name in entry_points {
+            let (index, entry_point) = module
+                .entry_points
+                .iter()
+                .enumerate()
+                .find(|(_, ep)| ep.name == *name)
+                .ok_or_else(|| format!("no entry point named {}", name))?;
+            let stage = match entry_point.stage {
+                ShaderStage::Vertex => ShaderStages::VERTEX,
+                ShaderStage::Fragment => ShaderStages::FRAGMENT,
+                ShaderStage::Compute => ShaderStages::COMPUTE,
+            };
+
+            let function_info = info.get_entry_point(index);
+            for (handle, var) in module.global_variables.iter() {
+                let Some(binding) = &var.binding else {
+                    continue;
+                };
+                if function_info[handle].is_empty() {
+                    continue;
+                }
+                let key = (binding.group, binding.binding);
+                if let Some(entry) = bindings.get_mut(&key) {
+                    entry.visibility |= stage;
+                    continue;
+                }
+                let var_name = var.name.as_deref().unwrap_or("<unnamed>");
+                let (ty, count) = binding_type(module, var.space, var.ty).map_err(|e| {
+                    format!(
+                        "{} (@group({}) @binding({})): {}",
+                        var_name, key.0, key.1, e
+                    )
+                })?;
+                bindings.insert(
+                    key,
+                    BindGroupLayoutEntry {
+                        binding: binding.binding,
+                        visibility: stage,
+                        ty,
+                        count,
+                    },
+                );
+            }
+
+            if entry_point.stage == ShaderStage::Vertex {
+                for argument in &entry_point.function.arguments {
+                    let arg_name = argument.name.as_deref().unwrap_or("<unnamed>");
+                    match (&argument.binding, &module.types[argument.ty].inner) {
+                        (Some(binding), inner) => {
+                            push_input(&mut reflection, arg_name, binding, inner)?
+                        }
+                        (None, TypeInner::Struct { members, .. }) => {
+                            for member in members {
+                                let member_name = member.name.as_deref().unwrap_or("<unnamed>");
+                                if let Some(binding) = &member.binding {
+                                    push_input(
+                                        &mut reflection,
+                                        member_name,
+                                        binding,
+                                        &module.types[member.ty].inner,
+                                    )?;
+                                }
+                            }
+                        }
+                        (None, _) => return Err(format!("{} has no binding", arg_name)),
+                    }
+                }
+            }
+        }
+
+        for ((group, _), entry) in bindings {
+            reflection.bind_groups.entry(group).or_default().push(entry);
+        }
+        reflection.vertex_inputs.sort_by_key(|input| input.location);
+        Ok(reflection)
+    }
+
+    /// The layout entries for `group`, which are empty if the shader doesn't
+    /// use it.
+    pub fn bind_group(&self, group: u32) -> &[BindGroupLayoutEntry] {
+        self.bind_groups.get(&group).map_or(&[], Vec::as_slice)
+    }
+
+    /// Checks that `layout`, which describes the Rust struct `name`, supplies
+    /// every input of the vertex entry point in the format it expects, and
+    /// reads it from where the struct keeps it. `size` is the struct's
+    /// `std::mem::size_of`, and `offsets` the `std::mem::offset_of` the field
+    /// each shader location is read from.
+    pub fn check_vertex_layout(
+        &self,
+        name: &str,
+        layout: &VertexBufferLayout,
+        size: usize,
+        offsets: &[(u32, usize)],
+    ) -> Result<(), EngineError> {
+        let mut mismatches = vec![];
+        if layout.array_stride != size as u64 {
+            mismatches.push(format!(
+                "the stride is {} bytes but {} is {}",
+                layout.array_stride, name, size
+            ));
+        }
+        for input in &self.vertex_inputs {
+            let Some(a) = layout
+                .attributes
+                .iter()
+                .find(|a| a.shader_location == input.location)
+            else {
+                mismatches.push(format!(
+                    "{} at location {} ({:?}) is missing from {}",
+                    input.name, input.location, input.format, name
+                ));
+                continue;
+            };
+            if a.format != input.format {
+                mismatches.push(format!(
+                    "{} at location {} is {:?} in the shader but {:?} in {}",
+                    input.name, input.location, input.format, a.format, name
+                ));
+            }
+            match offsets
+                .iter()
+                .find(|(location, _)| *location == input.location)
+            {
+                Some(&(_, offset)) if a.offset == offset as u64 => {}
+                Some(&(_, offset)) => mismatches.push(format!(
+                    "{} at location {} is read from offset {} but is at {} in {}",
+                    input.name, input.location, a.offset, offset, name
+                )),
+                None => mismatches.push(format!(
+                    "{} at location {} isn't a field of {}",
+                    input.name, input.location, name
+                )),
+            }
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(EngineError::Validation {
+                label: name.to_string(),
+                message: mismatches.join("; "),
+            })
+        }
+    }
+}
+
+impl fmt::Display for ShaderReflection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for input in &self.vertex_inputs {
+            writeln!(
+                f,
+                "@location({}) {}: {:?}",
+                input.location, input.name, input.format
+            )?;
+        }
+        for (group, entries) in &self.bind_groups {
+            for entry in entries {
+                writeln!(
+                    f,
+                    "@group({}) @binding({}) {:?}: {:?}",
+                    group, entry.binding, entry.visibility, entry.ty
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn push_input(
+    reflection: &mut ShaderReflection,
+    name: &str,
+    binding: &Binding,
+    inner: &TypeInner,
+) -> Result<(), String> {
+    // Builtins such as @builtin(vertex_index) don't come from a buffer
+    let Binding::Location { location, .. } = binding else {
+        return Ok(());
+    };
+    let format = vertex_format(inner).ok_or_else(|| {
+        format!(
+            "{} at location {} has a type no vertex format matches",
+            name, location
+        )
+    })?;
+    reflection.vertex_inputs.push(VertexInput {
+        name: name.to_string(),
+        location: *location,
+        format,
+    });
+    Ok(())
+}
+
+/// The vertex format that is read into a value of type `inner`. WGSL can't
+/// tell a normalized format from a float one, so 32-bit formats are assumed.
+fn vertex_format(inner: &TypeInner) -> Option<VertexFormat> {
+    let (size, scalar) = match *inner {
+        TypeInner::Scalar(scalar) => (None, scalar),
+        TypeInner::Vector { size, scalar } => (Some(size), scalar),
+        _ => return None,
+    };
+    if scalar.width != 4 {
+        return None;
+    }
+    use VertexFormat::*;
+    Some(match (scalar.kind, size) {
+        (ScalarKind::Float, None) => Float32,
+        (ScalarKind::Float, Some(VectorSize::Bi)) => Float32x2,
+        (ScalarKind::Float, Some(VectorSize::Tri)) => Float32x3,
+        (ScalarKind::Float, Some(VectorSize::Quad)) => Float32x4,
+        (ScalarKind::Sint, None) => Sint32,
+        (ScalarKind::Sint, Some(VectorSize::Bi)) => Sint32x2,
+        (ScalarKind::Sint, Some(VectorSize::Tri)) => Sint32x3,
+        (ScalarKind::Sint, Some(VectorSize::Quad)) => Sint32x4,
+        (ScalarKind::Uint, None) => Uint32,
+        (ScalarKind::Uint, Some(VectorSize::Bi)) => Uint32x2,
+        (ScalarKind::Uint, Some(VectorSize::Tri)) => Uint32x3,
+        (ScalarKind::Uint, Some(VectorSize::Quad)) => Uint32x4,
+        _ => return None,
+    })
+}
+
+/// The binding type and array count for a global of type `ty` in `space`.
+fn binding_type(
+    module: &Module,
+    space: AddressSpace,
+    ty: Handle<Type>,
+) -> Result<(BindingType, Option<std::num::NonZeroU32>), String> {
+    let inner = &module.types[ty].inner;
+    if let TypeInner::BindingArray { base, size } = *inner {
+        let count = match size {
+            ArraySize::Constant(count) => Some(count),
+            ArraySize::Dynamic => return Err("runtime-sized binding arrays are unsupported".into()),
+        };
+        return Ok((binding_type(module, space, base)?.0, count));
+    }
+
+    let ty = match (space, inner) {
+        (AddressSpace::Uniform, _) => BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: NonZeroU64::new(inner.size(module.to_ctx()).into()),
+        },
+        (AddressSpace::Storage { access }, _) => BindingType::Buffer {
+            ty: BufferBindingType::Storage {
+                read_only: !access.contains(StorageAccess::STORE),
+            },
+            has_dynamic_offset: false,
+            // The trailing array of a storage buffer can be any length
+            min_binding_size: None,
+        },
+        (_, TypeInner::Sampler { comparison }) => BindingType::Sampler(if *comparison {
+            SamplerBindingType::Comparison
+        } else {
+            SamplerBindingType::Filtering
+        }),
+        (
+            _,
+            TypeInner::Image {
+                dim,
+                arrayed,
+                class,
+            },
+        ) => {
+            let view_dimension = match (dim, arrayed) {
+                (ImageDimension::D1, _) => TextureViewDimension::D1,
+                (ImageDimension::D2, false) => TextureViewDimension::D2,
+                (ImageDimension::D2, true) => TextureViewDimension::D2Array,
+                (ImageDimension::D3, _) => TextureViewDimension::D3,
+                (ImageDimension::Cube, false) => TextureViewDimension::Cube,
+                (ImageDimension::Cube, true) => TextureViewDimension::CubeArray,
+            };
+            match *class {
+                // Whether a float texture is filterable depends on its format,
+                // which the shader doesn't say, so assume it is
+                ImageClass::Sampled { kind, multi } => BindingType::Texture {
+                    sample_type: match kind {
+                        ScalarKind::Sint => TextureSampleType::Sint,
+                        ScalarKind::Uint => TextureSampleType::Uint,
+                        _ => TextureSampleType::Float { filterable: !multi },
+                    },
+                    view_dimension,
+                    multisampled: multi,
+                },
+                ImageClass::Depth { multi } => BindingType::Texture {
+                    sample_type: TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                },
+                ImageClass::Storage { format, access } => BindingType::StorageTexture {
+                    access: match (
+                        access.contains(StorageAccess::LOAD),
+                        access.contains(StorageAccess::STORE),
+                    ) {
+                        (true, true) => StorageTextureAccess::ReadWrite,
+                        (true, false) => StorageTextureAccess::ReadOnly,
+                        _ => StorageTextureAccess::WriteOnly,
+                    },
+                    format: storage_format(format),
+                    view_dimension,
+                },
+            }
+        }
+        _ => return Err(format!("{:?} globals can't be bound", space)),
+    };
+    Ok((ty, None))
+}
+
+fn storage_format(format: StorageFormat) -> TextureFormat {
+    macro_rules! same_name {
+        ($($name:ident),* $(,)?) => {
+            match format {
+                $(StorageFormat::$name => TextureFormat::$name,)*
+            }
+        };
+    }
+    same_name!(
+        R8Unorm,
+        R8Snorm,
+        R8Uint,
+        R8Sint,
+        R16Uint,
+        R16Sint,
+        R16Float,
+        Rg8Unorm,
+        Rg8Snorm,
+        Rg8Uint,
+        Rg8Sint,
+        R32Uint,
+        R32Sint,
+        R32Float,
+        Rg16Uint,
+        Rg16Sint,
+        Rg16Float,
+        Rgba8Unorm,
+        Rgba8Snorm,
+        Rgba8Uint,
+        Rgba8Sint,
+        Bgra8Unorm,
+        Rgb10a2Uint,
+        Rgb10a2Unorm,
+        Rg11b10Float,
+        Rg32Uint,
+        Rg32Sint,
+        Rg32Float,
+        Rgba16Uint,
+        Rgba16Sint,
+        Rgba16Float,
+        Rgba32Uint,
+        Rgba32Sint,
+        Rgba32Float,
+        R16Unorm,
+        R16Snorm,
+        Rg16Unorm,
+        Rg16Snorm,
+        Rgba16Unorm,
+        Rgba16Snorm,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use naga::{
+        front::wgsl,
+        valid::{Capabilities, ValidationFlags, Validator},
+    };
+    use wgpu::{vertex_attr_array, VertexStepMode};
+
+    use super::*;
+
+    const SHADER: &str = "
+struct Camera {
+    view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+@group(1) @binding(0)
+var color_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var color_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput, @builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(in.position, 1.0);
+    out.tex_coords = in.tex_coords.xy;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(color_texture, color_sampler, in.tex_coords);
+}
+";
+
+    fn reflection() -> ShaderReflection {
+        let module = wgsl::parse_str(SHADER).unwrap();
+        let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .unwrap();
+        ShaderReflection::new(&module, &info, &["vs_main", "fs_main"]).unwrap()
+    }
+
+    #[test]
+    fn bindings_are_visible_to_the_stages_that_use_them() {
+        let reflection = reflection();
+        assert_eq!(
+            reflection.bind_group(0),
+            [BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: NonZeroU64::new(64),
+                },
+                count: None,
+            }]
+        );
+        assert_eq!(
+            reflection.bind_group(1),
+            [
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ]
+        );
+        assert_eq!(reflection.bind_group(2), []);
+    }
+
+    #[test]
+    fn vertex_layouts_must_match_the_inputs() {
+        let reflection = reflection();
+        let input = |name: &str, location, format| VertexInput {
+            name: name.into(),
+            location,
+            format,
+        };
+        assert_eq!(
+            reflection.vertex_inputs,
+            [
+                input("position", 0, VertexFormat::Float32x3),
+                input("tex_coords", 1, VertexFormat::Float32x4),
+            ]
+        );
+
+        let vec4 = vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+        let vec2 = vertex_attr_array![0 => Float32x3, 1 => Float32x2];
+        let check = |attributes, array_stride, size, offsets: &[(u32, usize)]| {
+            let layout = VertexBufferLayout {
+                array_stride,
+                step_mode: VertexStepMode::Vertex,
+                attributes,
+            };
+            reflection
+                .check_vertex_layout("Vertex", &layout, size, offsets)
+                .map_err(|e| match e {
+                    EngineError::Validation { message, .. } => message,
+                    e => panic!("{}", e),
+                })
+        };
+        assert_eq!(check(&vec4, 28, 28, &[(0, 0), (1, 12)]), Ok(()));
+        // Each way the layout can disagree with the struct or the shader
+        assert_eq!(
+            check(&vec2, 20, 20, &[(0, 0), (1, 12)]),
+            Err(
+                "tex_coords at location 1 is Float32x4 in the shader but Float32x2 in Vertex"
+                    .into()
+            )
+        );
+        assert_eq!(
+            check(&vec4, 32, 28, &[(0, 0), (1, 12)]),
+            Err("the stride is 32 bytes but Vertex is 28".into())
+        );
+        assert_eq!(
+            check(&vec4, 28, 28, &[(0, 0), (1, 16)]),
+            Err("tex_coords at location 1 is read from offset 12 but is at 16 in Vertex".into())
+        );
+        assert_eq!(
+            check(&vec4, 28, 28, &[(0, 0)]),
+            Err("tex_coords at location 1 isn't a field of Vertex".into())
+        );
+    }
+}
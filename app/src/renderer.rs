@@ -0,0 +1,158 @@
+use image::RgbaImage;
+
+use crate::{
+    bake::TextureData,
+    config::Config,
+    cpu::Mat4,
+    engine::{Engine, Mesh},
+    error::EngineError,
+    resources::{load_model, load_texture_data},
+};
+
+/// What the GPU and CPU backends have in common: a textured mesh drawn
+/// through a camera into a frame that can be read back. Code written against
+/// this draws the same image, give or take rounding, on either.
+///
+/// The futures are awaited where they're created, and wgpu's aren't `Send`
+/// on wasm, so the `async fn`s here don't promise to be.
+#[allow(async_fn_in_trait)]
+pub trait Renderer {
+    /// Replaces the mesh that's drawn.
+    fn upload_mesh(&mut self, mesh: &Mesh);
+
+    /// Replaces the texture the mesh is drawn with.
+    async fn upload_texture(&mut self, texture: &TextureData) -> Result<(), EngineError>;
+
+    /// Sets the matrix that takes the mesh's positions into clip space, the
+    /// view and projection combined.
+    fn set_camera(&mut self, view_proj: Mat4);
+
+    /// Draws a frame.
+    fn draw(&mut self) -> Result<(), EngineError>;
+
+    /// Copies the last frame back as an image.
+    async fn read_back(&mut self) -> Result<RgbaImage, EngineError>;
+}
+
+/// Everything a [`Renderer`] draws, kept apart from either backend.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub mesh: Mesh,
+    pub texture: TextureData,
+    pub camera: Mat4,
+}
+
+impl Scene {
+    /// Loads the scene the engine starts with: the model and texture named
+    /// in `config`, or the built-in square, seen through the identity camera.
+    pub async fn from_config(config: &Config) -> Result<Self, EngineError> {
+        Ok(Self {
+            mesh: match &config.model {
+                Some(model) => load_model(model).await?,
+                None => Mesh::square(),
+            },
+            texture: load_texture_data(&config.texture).await?,
+            camera: Mat4::IDENTITY,
+        })
+    }
+
+    /// Uploads the scene to `renderer`, draws it, and reads the frame back.
+    pub async fn render(&self, renderer: &mut impl Renderer) -> Result<RgbaImage, EngineError> {
+        renderer.upload_mesh(&self.mesh);
+        renderer.upload_texture(&self.texture).await?;
+        renderer.set_camera(self.camera);
+        renderer.draw()?;
+        renderer.read_back().await
+    }
+}
+
+/// Draws to the window's surface, or offscreen when headless. Surfaces can't
+/// be read, so reading back from a windowed engine draws the frame again
+/// offscreen.
+impl Renderer for Engine<'_> {
+    fn upload_mesh(&mut self, mesh: &Mesh) {
+        self.set_mesh(mesh.clone());
+    }
+
+    async fn upload_texture(&mut self, texture: &TextureData) -> Result<(), EngineError> {
+        self.set_texture(texture.clone(), "Renderer::upload_texture")
+            .await
+    }
+
+    fn set_camera(&mut self, view_proj: Mat4) {
+        Engine::set_camera(self, view_proj);
+    }
+
+    fn draw(&mut self) -> Result<(), EngineError> {
+        self.render()
+    }
+
+    async fn read_back(&mut self) -> Result<RgbaImage, EngineError> {
+        if self.window().is_some() {
+            self.render_to_image().await
+        } else {
+            self.read_offscreen().await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::{
+        compare::{compare, Thresholds},
+        cpu::{CpuRenderer, Vec3},
+    };
+
+    /// The square tilted away from a perspective camera and textured with a
+    /// fine checkerboard, so the far end is minified across several mip
+    /// levels.
+    fn tilted_square() -> Scene {
+        let checkers = RgbaImage::from_fn(64, 64, |x, y| {
+            if (x / 4 + y / 4) % 2 == 0 {
+                Rgba([230, 60, 40, 255])
+            } else {
+                Rgba([240, 240, 220, 255])
+            }
+        });
+        let view = Mat4::look_at(
+            Vec3::new(0.0, -2.2, 2.0),
+            Vec3::new(0.0, 0.3, 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        Scene {
+            mesh: Mesh::square(),
+            texture: TextureData::from_image(&checkers),
+            camera: Mat4::perspective(1.0, 1.5, 0.1, 10.0) * view,
+        }
+    }
+
+    #[test]
+    fn backends_draw_the_same_scene() {
+        let config = Config {
+            surface_width: 96,
+            surface_height: 64,
+            msaa: 4,
+            ..Config::default()
+        };
+        let scene = tilted_square();
+        let (gpu, mut cpu) = match pollster::block_on(async {
+            let mut engine = Engine::new_headless(&config).await?;
+            let cpu = CpuRenderer::for_engine(&engine);
+            Ok((scene.render(&mut engine).await?, cpu))
+        }) {
+            Err(EngineError::NoAdapter(e)) => {
+                eprintln!("Skipping the GPU comparison: {}", e);
+                return;
+            }
+            result => result.expect("GPU render failed"),
+        };
+        let cpu = pollster::block_on(scene.render(&mut cpu)).unwrap();
+
+        let metrics = compare(&gpu, &cpu).unwrap();
+        let failures = metrics.failures(&Thresholds::default());
+        assert!(failures.is_empty(), "{}: {}", metrics, failures.join(", "));
+    }
+}
@@ -23,71 +23,89 @@
 //
 // Copied and modified from code at https://github.com/sotrh/learn-wgpu
 
-use anyhow::Ok;
-
-use cfg_if::cfg_if;
-
+use image::DynamicImage;
 use wgpu::{Device, Queue};
 
-use crate::texture;
+use crate::{
+    bake::{self, FormatError, Kind, MeshData, TextureData},
+    engine::Mesh,
+    error::EngineError,
+    source::asset_source,
+    texture, tga,
+};
 
-#[cfg(target_arch = "wasm32")]
-fn format_url(file_name: &str) -> reqwest::Url {
-    let window = web_sys::window().unwrap();
-    let location = window.location();
-    let mut origin = location.origin().unwrap();
-    if !origin.ends_with("tinyrenderer_wgpu/assets") {
-        origin = format!("{}/tinyrenderer_wgpu/assets", origin);
-    }
-    let base = reqwest::Url::parse(&format!("{}/", origin,)).unwrap();
-    base.join(file_name).unwrap()
+/// Reads `file_name` from the configured [`AssetSource`](crate::source::AssetSource)
+/// as UTF-8 text.
+pub async fn load_string(file_name: &str) -> Result<String, EngineError> {
+    let data = load_binary(file_name).await?;
+    String::from_utf8(data).map_err(|e| EngineError::decode_failed(file_name, e))
 }
 
-pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            let url = format_url(file_name);
-            let txt = reqwest::get(url)
-                .await?
-                .text()
-                .await?;
-        } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
-            let txt = std::fs::read_to_string(path)?;
-        }
-    }
-
-    Ok(txt)
+/// Reads `file_name` from the configured [`AssetSource`](crate::source::AssetSource).
+pub async fn load_binary(file_name: &str) -> Result<Vec<u8>, EngineError> {
+    asset_source().read(file_name).await
 }
 
-pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            let url = format_url(file_name);
-            let data = reqwest::get(url)
-                .await?
-                .bytes()
-                .await?
-                .to_vec();
-        } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
-            let data = std::fs::read(path)?;
-        }
-    }
-
-    Ok(data)
+/// Reads and decodes the source image `file_name`. The asset pack and
+/// embedded assets only have the baked form of textures, so prefer
+/// [`load_texture_data`] for anything the build script bakes.
+pub async fn load_image(file_name: &str) -> Result<DynamicImage, EngineError> {
+    let data = load_binary(file_name).await?;
+    tga::load_from_memory(&data, file_name).map_err(|e| EngineError::decode_failed(file_name, e))
 }
 
+/// Uploads the source image `file_name` without mipmaps. Like
+/// [`load_image`], this needs the source, which only loose asset directories
+/// have.
 pub async fn load_texture(
     file_name: &str,
     is_normal_map: bool,
     device: &Device,
     queue: &Queue,
-) -> anyhow::Result<texture::Texture> {
+) -> Result<texture::Texture, EngineError> {
     let data = load_binary(file_name).await?;
     texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
 }
+
+/// Loads the baked form of `file_name`, or `None` if the build script didn't
+/// bake it.
+async fn load_baked(file_name: &str, kind: Kind) -> Result<Option<Vec<u8>>, EngineError> {
+    match load_binary(&bake::baked_path(file_name, kind)).await {
+        Ok(data) => Ok(Some(data)),
+        Err(EngineError::AssetNotFound { .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Loads an image along with its mip chain, preferring the copy baked by the
+/// build script and building the chain at runtime if there isn't one.
+pub async fn load_texture_data(file_name: &str) -> Result<TextureData, EngineError> {
+    match load_baked(file_name, Kind::Texture).await? {
+        Some(data) => {
+            TextureData::decode(&data).map_err(|e| EngineError::decode_failed(file_name, e))
+        }
+        None => {
+            log::warn!("{} wasn't baked, building its mipmaps now", file_name);
+            Ok(TextureData::from_image(
+                &load_image(file_name).await?.to_rgba8(),
+            ))
+        }
+    }
+}
+
+/// Loads a mesh as an indexed triangle list, preferring the copy baked by the
+/// build script. Unbaked OBJ files are parsed at runtime; glTF files have to
+/// be baked. Materials are ignored.
+pub async fn load_model(file_name: &str) -> Result<Mesh, EngineError> {
+    let data = match load_baked(file_name, Kind::Mesh).await? {
+        Some(data) => MeshData::decode(&data),
+        None if file_name.to_ascii_lowercase().ends_with(".obj") => {
+            MeshData::from_obj(&load_string(file_name).await?)
+        }
+        None => Err(FormatError(
+            "only OBJ meshes can be loaded without baking".into(),
+        )),
+    }
+    .map_err(|e| EngineError::decode_failed(file_name, e))?;
+    Ok(data.into())
+}
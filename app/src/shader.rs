@@ -0,0 +1,271 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+};
+
+use naga::{
+    front::wgsl,
+    valid::{Capabilities, ModuleInfo, ValidationFlags, Validator},
+    Module,
+};
+
+use crate::{error::EngineError, preprocess, reflect::ShaderReflection, resources::load_string};
+
+/// Entry point names and binding indices for each shader under `res/`,
+/// written by the build script after it has validated the shader, along with
+/// the shaders themselves.
+pub mod generated {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+/// The asset path of the engine's shader.
+pub const ENTRY: &str = generated::shaders_shader::PATH;
+
+/// Names of the entry points the engine's pipeline uses.
+pub const VERTEX_ENTRY: &str = generated::shaders_shader::VS_MAIN;
+pub const FRAGMENT_ENTRY: &str = generated::shaders_shader::FS_MAIN;
+
+/// The engine's shaders, as they were when the crate was compiled, keyed by
+/// asset path.
+const BUILTIN_FILES: &[(&str, &str)] = generated::BUILTIN_FILES;
+
+/// Where the assets live in the source tree, for loading shaders from disk
+/// while developing.
+#[cfg(not(target_arch = "wasm32"))]
+pub const SOURCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/res");
+
+/// WGSL source, along with a label used in diagnostics and for the shader
+/// module.
+#[derive(Clone, Debug)]
+pub struct ShaderSource {
+    pub label: String,
+    pub code: Cow<'static, str>,
+    /// The asset paths of every file the code was assembled from, starting
+    /// with the entry point.
+    pub files: Vec<String>,
+    /// For each line of the code, the index in `files` of the file it came
+    /// from and its line number there.
+    pub lines: Vec<(usize, usize)>,
+}
+
+impl ShaderSource {
+    /// The engine's shader, preprocessed from the copy compiled into the
+    /// binary.
+    pub fn builtin() -> Result<Self, EngineError> {
+        Preprocessor::new().process(ENTRY, |path| {
+            BUILTIN_FILES
+                .iter()
+                .find(|(name, _)| *name == path)
+                .map(|(_, code)| Cow::Borrowed(*code))
+                .ok_or_else(|| EngineError::asset_not_found(path, "not a builtin shader"))
+        })
+    }
+
+    /// Parses and validates the source with naga, so mistakes are reported
+    /// with line and column numbers before wgpu sees them. Once anything is
+    /// included, naga's line numbers are in the expanded code, so they're
+    /// followed by where each labelled span came from.
+    pub fn validate(&self) -> Result<(Module, ModuleInfo), EngineError> {
+        let module = wgsl::parse_str(&self.code).map_err(|e| {
+            let labels = e
+                .labels()
+                .filter_map(|(span, label)| Some((span.to_range()?, label)));
+            self.compile_error(|path| e.emit_to_string_with_path(&self.code, path), labels)
+        })?;
+        let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .map_err(|e| {
+                let labels = e
+                    .spans()
+                    .filter_map(|(span, label)| Some((span.to_range()?, label.as_str())));
+                self.compile_error(|path| e.emit_to_string_with_path(&self.code, path), labels)
+            })?;
+        Ok((module, info))
+    }
+
+    /// Turns naga's diagnostic, emitted by `emit` with the path to show, into
+    /// an error, locating `labels` in the original files if the code isn't
+    /// all from one.
+    fn compile_error<'l>(
+        &self,
+        emit: impl FnOnce(&str) -> String,
+        labels: impl Iterator<Item = (Range<usize>, &'l str)>,
+    ) -> EngineError {
+        let message = match self.files.len() {
+            1 => emit(&self.label),
+            _ => {
+                let mut message = emit(&format!("{} (expanded)", self.label));
+                message.push_str(&preprocess::locate(
+                    &self.code,
+                    &self.files,
+                    &self.lines,
+                    labels,
+                ));
+                message
+            }
+        };
+        EngineError::ShaderCompile {
+            label: self.label.clone(),
+            message,
+        }
+    }
+
+    /// Validates the source and reflects the engine's vertex and fragment
+    /// entry points.
+    pub fn reflect(&self) -> Result<ShaderReflection, EngineError> {
+        let (module, info) = self.validate()?;
+        ShaderReflection::new(&module, &info, &[VERTEX_ENTRY, FRAGMENT_ENTRY]).map_err(|message| {
+            EngineError::Validation {
+                label: self.label.clone(),
+                message,
+            }
+        })
+    }
+
+    pub fn descriptor(&self) -> wgpu::ShaderModuleDescriptor<'_> {
+        wgpu::ShaderModuleDescriptor {
+            label: Some(&self.label),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(&self.code)),
+        }
+    }
+}
+
+/// A small C-style preprocessor for WGSL. It understands:
+///
+/// - `#include "file.wgsl"`, resolved relative to the including file's
+///   directory using the same asset paths as [`load_string`]. Each file is
+///   only included once, so shared structs can be included from anywhere.
+/// - `#define NAME` and `#define NAME value`, after which `NAME` is replaced
+///   by `value` wherever it appears as a whole word, and `#undef NAME`.
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif`.
+///
+/// Defines added with [`Preprocessor::define`] apply before the first line,
+/// so one set of files can be specialized for each pipeline.
+#[derive(Clone, Debug, Default)]
+pub struct Preprocessor {
+    defines: BTreeMap<String, String>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.defines.insert(name.into(), value.to_string());
+        self
+    }
+
+    /// Loads `entry` and everything it includes with [`load_string`], and
+    /// preprocesses them.
+    pub async fn load(&self, entry: &str) -> Result<ShaderSource, EngineError> {
+        // Loading is async and preprocessing isn't, so fetch every file that
+        // could be included first, whether or not its #include is live
+        let mut files = HashMap::new();
+        let mut pending = vec![entry.to_string()];
+        while let Some(path) = pending.pop() {
+            if files.contains_key(&path) {
+                continue;
+            }
+            let code = load_string(&path).await?;
+            pending.extend(preprocess::includes(&path, &code));
+            files.insert(path, code);
+        }
+        self.process(entry, |path| {
+            files
+                .get(path)
+                .map(|code| Cow::Borrowed(code.as_str()))
+                .ok_or_else(|| EngineError::asset_not_found(path, "include was not loaded"))
+        })
+    }
+
+    /// Preprocesses `entry`, calling `read` to get the contents of it and of
+    /// each file it includes.
+    pub fn process<'a>(
+        &self,
+        entry: &str,
+        read: impl FnMut(&str) -> Result<Cow<'a, str>, EngineError>,
+    ) -> Result<ShaderSource, EngineError> {
+        let output = preprocess::expand(entry, &self.defines, read, |message| {
+            EngineError::ShaderCompile {
+                label: entry.to_string(),
+                message,
+            }
+        })?;
+        Ok(ShaderSource {
+            label: entry.to_string(),
+            code: Cow::Owned(output.code),
+            files: output.files,
+            lines: output.lines,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: &str = "\
+#include \"lib.wgsl\"
+#ifdef UNUSED
+fn unused() {}
+#endif
+
+fn main() -> f32 {
+    return helper();
+}
+";
+    const LIB: &str = "\
+fn helper() -> f32 {
+    return undefined_name;
+}
+";
+
+    fn source() -> ShaderSource {
+        Preprocessor::new()
+            .process("shaders/main.wgsl", |path| match path {
+                "shaders/main.wgsl" => Ok(Cow::Borrowed(MAIN)),
+                "shaders/lib.wgsl" => Ok(Cow::Borrowed(LIB)),
+                _ => Err(EngineError::asset_not_found(path, "not in the test")),
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn expanded_lines_map_back_to_their_files() {
+        let source = source();
+        let lines: Vec<_> = source
+            .lines
+            .iter()
+            .map(|&(file, line)| (source.files[file].as_str(), line))
+            .collect();
+        let main = |line| ("shaders/main.wgsl", line);
+        let lib = |line| ("shaders/lib.wgsl", line);
+        let mut expected = vec![main(1), lib(1), lib(2), lib(3)];
+        expected.extend((2..=8).map(main));
+        assert_eq!(lines, expected);
+        // Directives and dead lines are blank
+        let code: Vec<_> = source.code.lines().collect();
+        assert_eq!(code[0], "");
+        assert_eq!(&code[4..7], ["", "", ""]);
+        assert_eq!(code[9], "    return helper();");
+    }
+
+    #[test]
+    fn errors_point_at_the_file_they_are_in() {
+        let Err(EngineError::ShaderCompile { message, .. }) = source().validate() else {
+            panic!("the shader should not compile");
+        };
+        assert!(
+            message.contains("shaders/main.wgsl (expanded)"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("= at shaders/lib.wgsl:2:12:"),
+            "{}",
+            message
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use crate::{
+    error::EngineError,
+    pack::{self, Index},
+};
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+/// The future returned by [`AssetSource::read`]. It isn't `Send`, because
+/// fetches in the browser aren't.
+pub type ReadFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>, EngineError>> + 'a>>;
+
+/// Somewhere assets are read from. Paths are relative to the root of the
+/// source and always separated by `/`.
+pub trait AssetSource: Send + Sync {
+    /// Reads the whole asset at `path`, failing with
+    /// [`EngineError::AssetNotFound`] if there's no such asset.
+    fn read<'a>(&'a self, path: &'a str) -> ReadFuture<'a>;
+}
+
+static SOURCE: RwLock<Option<Arc<dyn AssetSource>>> = RwLock::new(None);
+
+/// Makes every later load read from `source`. Meant to be called once at
+/// startup, before anything is loaded.
+pub fn set_asset_source(source: impl AssetSource + 'static) {
+    *SOURCE.write().unwrap() = Some(Arc::new(source));
+}
+
+/// The source assets are currently read from. Unless
+/// [`set_asset_source`] says otherwise, that's `EMBEDDED_ASSETS` when built
+/// with the `embed-assets` feature, or else the resources copied by the build
+/// script on native and, on the web, the build script's pack with
+/// [`HttpSource::from_page`] for anything that isn't in it.
+pub fn asset_source() -> Arc<dyn AssetSource> {
+    if let Some(source) = SOURCE.read().unwrap().as_ref() {
+        return source.clone();
+    }
+    SOURCE
+        .write()
+        .unwrap()
+        .get_or_insert_with(default_source)
+        .clone()
+}
+
+/// Every file the build script put in the resource directory, with baked
+/// assets in place of their sources.
+#[cfg(feature = "embed-assets")]
+pub static EMBEDDED_ASSETS: EmbeddedSource =
+    EmbeddedSource::new(include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs")));
+
+fn default_source() -> Arc<dyn AssetSource> {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "embed-assets")] {
+            Arc::new(EMBEDDED_ASSETS.clone())
+        } else if #[cfg(target_arch = "wasm32")] {
+            let loose = HttpSource::from_page();
+            let packed = match loose.base().join(pack::FILE_NAME) {
+                Ok(url) => PackSource::new(HttpPack::new(url)),
+                Err(e) => panic!("asset base URL is invalid: {}", e),
+            };
+            Arc::new(LayeredSource::new(vec![Box::new(packed), Box::new(loose)]))
+        } else {
+            Arc::new(FileSource::new(
+                std::path::Path::new(env!("OUT_DIR")).join("res"),
+            ))
+        }
+    }
+}
+
+/// Reads assets from a directory.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug)]
+pub struct FileSource {
+    root: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileSource {
+    pub fn new(root: impl Into<std::path::PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AssetSource for FileSource {
+    fn read<'a>(&'a self, path: &'a str) -> ReadFuture<'a> {
+        Box::pin(async move {
+            std::fs::read(self.root.join(path)).map_err(|e| {
+                if e.kind() == std::io::ErrorKind::InvalidData {
+                    EngineError::decode_failed(path, e)
+                } else {
+                    EngineError::asset_not_found(path, e)
+                }
+            })
+        })
+    }
+}
+
+// Snippets are written to `<out-dir>/snippets/<crate>/`, and the Makefile
+// exports the assets to the out dir, next to the module that loads the wasm
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(inline_js = r#"
+export function asset_base_url() {
+    const base = globalThis.tinyrendererAssetBase;
+    return base === undefined
+        ? new URL("../../", import.meta.url).href
+        : new URL(base, document.baseURI).href;
+}
+"#)]
+extern "C" {
+    fn asset_base_url() -> String;
+}
+
+/// Fetches assets relative to a base URL. Only available on the web.
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone, Debug)]
+pub struct HttpSource {
+    base: reqwest::Url,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl HttpSource {
+    /// Fetches assets from under `base`, which is treated as a directory
+    /// whether or not it ends in `/`.
+    pub fn new(mut base: reqwest::Url) -> Self {
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+        Self { base }
+    }
+
+    /// Fetches assets from the URL the page puts in
+    /// `globalThis.tinyrendererAssetBase` before loading the module, or from
+    /// the directory the module was loaded from if it doesn't.
+    pub fn from_page() -> Self {
+        Self::new(reqwest::Url::parse(&asset_base_url()).expect("asset base URL is invalid"))
+    }
+
+    pub fn base(&self) -> &reqwest::Url {
+        &self.base
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl AssetSource for HttpSource {
+    fn read<'a>(&'a self, path: &'a str) -> ReadFuture<'a> {
+        Box::pin(async move {
+            let url = self
+                .base
+                .join(path)
+                .map_err(|e| EngineError::asset_not_found(path, e))?;
+            let response = reqwest::get(url)
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| EngineError::asset_not_found(path, e))?;
+            let bytes = response
+                .bytes()
+                .await
+                .map_err(|e| EngineError::asset_not_found(path, e))?;
+            Ok(bytes.to_vec())
+        })
+    }
+}
+
+/// Reads assets compiled into the binary, e.g. with `include_bytes!`.
+#[derive(Clone, Debug)]
+pub struct EmbeddedSource {
+    files: &'static [(&'static str, &'static [u8])],
+}
+
+impl EmbeddedSource {
+    /// `files` pairs each asset path with its contents.
+    pub const fn new(files: &'static [(&'static str, &'static [u8])]) -> Self {
+        Self { files }
+    }
+}
+
+impl AssetSource for EmbeddedSource {
+    fn read<'a>(&'a self, path: &'a str) -> ReadFuture<'a> {
+        let file = self.files.iter().find(|(name, _)| *name == path);
+        Box::pin(async move {
+            file.map(|(_, bytes)| bytes.to_vec())
+                .ok_or_else(|| EngineError::asset_not_found(path, "not embedded"))
+        })
+    }
+}
+
+/// Reads assets added at runtime, such as ones generated by the app or
+/// dropped onto the page.
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    files: RwLock<HashMap<String, Arc<[u8]>>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the asset at `path`, replacing any that was there.
+    pub fn insert(&self, path: impl Into<String>, bytes: impl Into<Arc<[u8]>>) {
+        self.files
+            .write()
+            .unwrap()
+            .insert(path.into(), bytes.into());
+    }
+
+    pub fn remove(&self, path: &str) {
+        self.files.write().unwrap().remove(path);
+    }
+}
+
+impl AssetSource for MemorySource {
+    fn read<'a>(&'a self, path: &'a str) -> ReadFuture<'a> {
+        let file = self.files.read().unwrap().get(path).cloned();
+        Box::pin(async move {
+            file.map(|bytes| bytes.to_vec())
+                .ok_or_else(|| EngineError::asset_not_found(path, "not in memory"))
+        })
+    }
+}
+
+/// Lets a source be shared, e.g. to keep adding to a [`MemorySource`] after
+/// handing it to [`set_asset_source`].
+impl<S: AssetSource + ?Sized> AssetSource for Arc<S> {
+    fn read<'a>(&'a self, path: &'a str) -> ReadFuture<'a> {
+        (**self).read(path)
+    }
+}
+
+/// Tries each of a list of sources in turn, moving on to the next one only
+/// when an asset isn't found.
+pub struct LayeredSource {
+    layers: Vec<Box<dyn AssetSource>>,
+}
+
+impl LayeredSource {
+    /// Reads from `layers`, earliest first.
+    pub fn new(layers: Vec<Box<dyn AssetSource>>) -> Self {
+        Self { layers }
+    }
+}
+
+impl AssetSource for LayeredSource {
+    fn read<'a>(&'a self, path: &'a str) -> ReadFuture<'a> {
+        Box::pin(async move {
+            let mut not_found = EngineError::asset_not_found(path, "there are no sources");
+            for layer in &self.layers {
+                match layer.read(path).await {
+                    Err(e @ EngineError::AssetNotFound { .. }) => not_found = e,
+                    result => return result,
+                }
+            }
+            Err(not_found)
+        })
+    }
+}
+
+/// Reads byte ranges of a pack written by the build script.
+pub trait PackReader: Send + Sync {
+    /// Where the pack is, for error messages.
+    fn name(&self) -> &str;
+
+    /// Reads `len` bytes starting `offset` bytes into the pack.
+    fn read_range(&self, offset: u64, len: u64) -> ReadFuture<'_>;
+}
+
+/// Reads assets out of a single pack file, see [`pack::Index`]. The index is
+/// read on the first load, and only the ranges holding requested assets are
+/// read after that.
+pub struct PackSource<R> {
+    reader: R,
+    index: Mutex<Option<Result<Arc<Index>, Arc<EngineError>>>>,
+}
+
+impl<R: PackReader> PackSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            index: Mutex::new(None),
+        }
+    }
+
+    /// The index, read if this is the first call. A failure to read it is
+    /// kept, so that a missing pack costs one request rather than one per
+    /// asset.
+    async fn index(&self) -> Result<Arc<Index>, Arc<EngineError>> {
+        if let Some(index) = self.index.lock().unwrap().clone() {
+            return index;
+        }
+        let index = self.read_index().await.map(Arc::new).map_err(Arc::new);
+        *self.index.lock().unwrap() = Some(index.clone());
+        index
+    }
+
+    async fn read_index(&self) -> Result<Index, EngineError> {
+        let name = self.reader.name();
+        let header = self.reader.read_range(0, pack::HEADER_LEN).await?;
+        let len =
+            Index::len_from_header(&header).map_err(|e| EngineError::decode_failed(name, e))?;
+        let bytes = self.reader.read_range(pack::HEADER_LEN, len).await?;
+        Index::decode(&bytes).map_err(|e| EngineError::decode_failed(name, e))
+    }
+}
+
+impl<R: PackReader> AssetSource for PackSource<R> {
+    fn read<'a>(&'a self, path: &'a str) -> ReadFuture<'a> {
+        Box::pin(async move {
+            let index = self
+                .index()
+                .await
+                .map_err(|e| EngineError::asset_not_found(path, e))?;
+            let (offset, len) = index
+                .get(path)
+                .ok_or_else(|| EngineError::asset_not_found(path, "not in the pack"))?;
+            self.reader.read_range(offset, len).await
+        })
+    }
+}
+
+/// A pack file mapped into memory.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct MappedPack {
+    name: String,
+    map: memmap2::Mmap,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl MappedPack {
+    /// Maps the pack at `path` into memory. The file must not be modified
+    /// while the engine runs, so don't rebuild a pack that's in use.
+    pub fn open(path: &std::path::Path) -> Result<Self, EngineError> {
+        let name = path.display().to_string();
+        let file = std::fs::File::open(path).map_err(|e| EngineError::asset_not_found(&name, e))?;
+        // SAFETY: nothing stops another process writing to the file while
+        // it's mapped, so the caller must not modify the pack while the
+        // engine runs, as documented above
+        let map = unsafe { memmap2::Mmap::map(&file) }
+            .map_err(|e| EngineError::asset_not_found(&name, e))?;
+        Ok(Self { name, map })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PackReader for MappedPack {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_range(&self, offset: u64, len: u64) -> ReadFuture<'_> {
+        let range = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(len).ok())
+            .and_then(|(start, len)| self.map.get(start..start.checked_add(len)?));
+        Box::pin(async move {
+            range
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| EngineError::decode_failed(&self.name, "range is out of bounds"))
+        })
+    }
+}
+
+/// A pack file fetched a range at a time over HTTP. Only available on the
+/// web.
+#[cfg(target_arch = "wasm32")]
+pub struct HttpPack {
+    name: String,
+    url: reqwest::Url,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl HttpPack {
+    pub fn new(url: reqwest::Url) -> Self {
+        Self {
+            name: url.to_string(),
+            url,
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl PackReader for HttpPack {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_range(&self, offset: u64, len: u64) -> ReadFuture<'_> {
+        Box::pin(async move {
+            if len == 0 {
+                return Ok(Vec::new());
+            }
+            let response = reqwest::Client::new()
+                .get(self.url.clone())
+                .header(
+                    reqwest::header::RANGE,
+                    format!("bytes={}-{}", offset, offset + len - 1),
+                )
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| EngineError::asset_not_found(&self.name, e))?;
+            let partial = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+            let bytes = response
+                .bytes()
+                .await
+                .map_err(|e| EngineError::asset_not_found(&self.name, e))?;
+            // A server that doesn't support ranges sends the whole pack
+            let range = if partial {
+                Some(&bytes[..])
+            } else {
+                bytes.get(offset as usize..(offset + len) as usize)
+            };
+            range
+                .filter(|range| range.len() as u64 == len)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| EngineError::decode_failed(&self.name, "range is out of bounds"))
+        })
+    }
+}
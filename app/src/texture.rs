@@ -23,14 +23,20 @@
 //
 // Copied and modified from code at https://github.com/sotrh/learn-wgpu
 
-use anyhow::*;
-use image::{load_from_memory, DynamicImage, GenericImageView};
+use std::iter::once;
+
+use futures_intrusive::channel::shared::oneshot_channel;
+use image::{DynamicImage, GenericImageView, RgbaImage};
 use wgpu::{
-    AddressMode, Device, Extent3d, FilterMode, Sampler, SamplerDescriptor, TextureDescriptor,
+    AddressMode, BufferAddress, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Device,
+    Extent3d, FilterMode, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, Maintain, MapMode,
+    Origin3d, Queue, Sampler, SamplerDescriptor, TextureAspect, TextureDescriptor,
     TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
-    TextureViewDimension,
+    TextureViewDimension, COPY_BYTES_PER_ROW_ALIGNMENT,
 };
 
+use crate::{bake::TextureData, error::EngineError, tga};
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: TextureView,
@@ -123,8 +129,9 @@ impl Texture {
         bytes: &[u8],
         label: &str,
         is_normal_map: bool,
-    ) -> Result<Self> {
-        let img = load_from_memory(bytes)?;
+    ) -> Result<Self, EngineError> {
+        let img = tga::load_from_memory(bytes, label)
+            .map_err(|e| EngineError::decode_failed(label, e))?;
         Self::from_image(device, queue, &img, Some(label), is_normal_map)
     }
 
@@ -134,7 +141,7 @@ impl Texture {
         img: &DynamicImage,
         label: Option<&str>,
         is_normal_map: bool,
-    ) -> Result<Self> {
+    ) -> Result<Self, EngineError> {
         println!("from_image img.color(): {:#?}", img.color());
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -178,6 +185,166 @@ impl Texture {
         Ok(texture)
     }
 
+    /// Uploads a texture and every level of its mip chain as `format`, which
+    /// should be one of the RGBA8 formats. Like [`Texture::from_image`] it
+    /// isn't filtered when magnified, but it is filtered trilinearly when
+    /// minified.
+    pub fn from_texture_data(
+        device: &Device,
+        queue: &Queue,
+        data: &TextureData,
+        format: TextureFormat,
+        label: Option<&str>,
+    ) -> Self {
+        let size = Extent3d {
+            width: data.width,
+            height: data.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label,
+            size,
+            mip_level_count: data.levels.len() as u32,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let texture = Self {
+            texture,
+            view,
+            sampler,
+            size,
+        };
+        texture.write_texture_data(queue, data);
+        texture
+    }
+
+    /// Replaces the contents of a texture created by
+    /// [`Texture::from_texture_data`], keeping the texture itself so that
+    /// bind groups using it see the new texels. Returns `false`, and writes
+    /// nothing, if `data` doesn't have the same size and number of mip levels.
+    pub fn write_texture_data(&self, queue: &Queue, data: &TextureData) -> bool {
+        if self.size.width != data.width
+            || self.size.height != data.height
+            || self.texture.mip_level_count() != data.levels.len() as u32
+        {
+            return false;
+        }
+        for (level, pixels) in data.levels.iter().enumerate() {
+            let (width, height) = data.level_size(level);
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &self.texture,
+                    mip_level: level as u32,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                pixels,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        true
+    }
+
+    /// Copies the first mip level of a 2D RGBA or BGRA texture back to the CPU.
+    /// The texture must have been created with [`TextureUsages::COPY_SRC`].
+    pub async fn to_image(&self, device: &Device, queue: &Queue) -> Result<RgbaImage, EngineError> {
+        let format = self.texture.format();
+        let bgra = match format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            _ => {
+                return Err(EngineError::Readback(format!(
+                    "can't read back a texture with format {:?}",
+                    format
+                )))
+            }
+        };
+
+        let Extent3d { width, height, .. } = self.size;
+        let unpadded_bytes_per_row = 4 * width;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT)
+            * COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Texture::to_image buffer"),
+            size: (padded_bytes_per_row * height) as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Texture::to_image encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (tx, rx) = oneshot_channel();
+        slice.map_async(MapMode::Read, move |result| {
+            tx.send(result).ok();
+        });
+        device.poll(Maintain::Wait);
+        rx.receive()
+            .await
+            .ok_or_else(|| EngineError::Readback("buffer was dropped before it was mapped".into()))?
+            .map_err(|e| EngineError::Readback(e.to_string()))?;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        buffer.unmap();
+
+        if bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| EngineError::Readback("read back a buffer of the wrong size".into()))
+    }
+
     pub fn create_texture(
         device: &Device,
         label: Option<&str>,
@@ -239,6 +406,44 @@ impl Texture {
             mag_filter,
         )
     }
+
+    /// Creates a render attachment with `sample_count` samples per pixel, to be
+    /// resolved into a single-sampled target at the end of a render pass.
+    pub fn create_multisampled_texture(
+        device: &Device,
+        width: u32,
+        height: u32,
+        format: TextureFormat,
+        sample_count: u32,
+        label: Option<&str>,
+    ) -> Self {
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label,
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+            size,
+        }
+    }
 }
 
 pub struct CubeTexture {
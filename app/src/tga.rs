@@ -0,0 +1,363 @@
+use std::path::Path;
+
+use image::{
+    error::{DecodingError, EncodingError, ImageFormatHint},
+    DynamicImage, GrayImage, ImageError, ImageFormat, ImageResult, RgbImage, RgbaImage,
+};
+
+use crate::bake::FormatError;
+
+const HEADER_LEN: usize = 18;
+
+/// The TGA 2.0 footer, with no developer or extension areas, that
+/// tinyrenderer ends its files with.
+const FOOTER: &[u8; 26] = b"\0\0\0\0\0\0\0\0TRUEVISION-XFILE.\0";
+
+/// Set in the image descriptor when the first row in the file is the top one.
+const TOP_TO_BOTTOM: u8 = 0x20;
+/// Set in the image descriptor when each row is stored right to left.
+const RIGHT_TO_LEFT: u8 = 0x10;
+
+/// The most pixels one RLE packet can hold.
+const MAX_PACKET: usize = 128;
+
+/// Which corner of the image a TGA file stores first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Origin {
+    /// Rows from the bottom up, as tinyrenderer writes them by default so
+    /// that y points up.
+    #[default]
+    BottomLeft,
+    TopLeft,
+}
+
+/// How [`encode`] writes an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Options {
+    pub rle: bool,
+    pub origin: Origin,
+}
+
+impl Default for Options {
+    /// tinyrenderer's defaults: RLE compressed, bottom row first.
+    fn default() -> Self {
+        Self {
+            rle: true,
+            origin: Origin::BottomLeft,
+        }
+    }
+}
+
+/// Encodes `image` as an uncompressed or RLE compressed TGA, byte for byte
+/// as tinyrenderer's `TGAImage::write_tga_file` does. Grayscale, RGB and
+/// RGBA images keep their channels; anything else is written as RGBA.
+///
+/// tinyrenderer's framebuffers are RGB, so convert renders with
+/// [`DynamicImage::to_rgb8`] first to get files identical to its output.
+pub fn encode(image: &DynamicImage, options: Options) -> Result<Vec<u8>, FormatError> {
+    let (width, height) = (image.width(), image.height());
+    let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => {
+            return Err(FormatError(format!(
+                "{}x{} is too large for a TGA",
+                width, height
+            )))
+        }
+    };
+    // TGA stores color channels as BGR(A)
+    let (bytes_per_pixel, mut data) = match image {
+        DynamicImage::ImageLuma8(image) => (1, image.as_raw().clone()),
+        DynamicImage::ImageRgb8(image) => (3, image.as_raw().clone()),
+        image => (4, image.to_rgba8().into_raw()),
+    };
+    if bytes_per_pixel > 1 {
+        data.chunks_exact_mut(bytes_per_pixel)
+            .for_each(|pixel| pixel.swap(0, 2));
+    }
+    if options.origin == Origin::BottomLeft {
+        data = data
+            .rchunks_exact((usize::from(width) * bytes_per_pixel).max(1))
+            .flatten()
+            .copied()
+            .collect();
+    }
+
+    let image_type = match (bytes_per_pixel, options.rle) {
+        (1, false) => 3,
+        (1, true) => 11,
+        (_, false) => 2,
+        (_, true) => 10,
+    };
+    let mut out = Vec::with_capacity(HEADER_LEN + data.len() + FOOTER.len());
+    out.extend_from_slice(&[0, 0, image_type]);
+    // No color map, and an origin of 0, 0
+    out.extend_from_slice(&[0; 9]);
+    out.extend_from_slice(&width.to_le_bytes());
+    out.extend_from_slice(&height.to_le_bytes());
+    out.push(bytes_per_pixel as u8 * 8);
+    out.push(match options.origin {
+        Origin::BottomLeft => 0,
+        Origin::TopLeft => TOP_TO_BOTTOM,
+    });
+    if options.rle {
+        write_rle(&mut out, &data, bytes_per_pixel);
+    } else {
+        out.extend_from_slice(&data);
+    }
+    out.extend_from_slice(FOOTER);
+    Ok(out)
+}
+
+/// Splits `data` into packets as tinyrenderer does: a run of identical
+/// pixels becomes a repeat packet, and everything between runs goes into raw
+/// packets. Packets carry on across rows.
+fn write_rle(out: &mut Vec<u8>, data: &[u8], bytes_per_pixel: usize) {
+    let pixels: Vec<&[u8]> = data.chunks_exact(bytes_per_pixel).collect();
+    let mut start = 0;
+    while start < pixels.len() {
+        let mut len = 1;
+        let mut raw = true;
+        while start + len < pixels.len() && len < MAX_PACKET {
+            let same = pixels[start + len - 1] == pixels[start + len];
+            if len == 1 {
+                raw = !same;
+            }
+            if raw && same {
+                // Leave the pixel that starts the run for the next packet
+                len -= 1;
+                break;
+            }
+            if !raw && !same {
+                break;
+            }
+            len += 1;
+        }
+        if raw {
+            out.push(len as u8 - 1);
+            out.extend(pixels[start..start + len].iter().copied().flatten());
+        } else {
+            out.push(len as u8 + 127);
+            out.extend_from_slice(pixels[start]);
+        }
+        start += len;
+    }
+}
+
+/// Decodes an uncompressed or RLE compressed grayscale, RGB or RGBA TGA,
+/// whichever corner it starts from, into an image whose first row is the
+/// top one.
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage, FormatError> {
+    let header = bytes
+        .get(..HEADER_LEN)
+        .ok_or_else(|| FormatError("missing TGA header".into()))?;
+    let (id_len, color_map, image_type) = (header[0], header[1], header[2]);
+    let width = usize::from(u16::from_le_bytes([header[12], header[13]]));
+    let height = usize::from(u16::from_le_bytes([header[14], header[15]]));
+    let (depth, descriptor) = (header[16], header[17]);
+    if color_map != 0 {
+        return Err(FormatError("color-mapped TGAs aren't supported".into()));
+    }
+    let rle = match image_type {
+        2 | 3 => false,
+        10 | 11 => true,
+        _ => return Err(FormatError(format!("unsupported TGA type {}", image_type))),
+    };
+    let bytes_per_pixel = match (image_type, depth) {
+        (3 | 11, 8) => 1,
+        (2 | 10, 24) => 3,
+        (2 | 10, 32) => 4,
+        _ => {
+            return Err(FormatError(format!(
+                "unsupported {}-bit TGA of type {}",
+                depth, image_type
+            )))
+        }
+    };
+
+    let len = width * height * bytes_per_pixel;
+    let body = &bytes[(HEADER_LEN + usize::from(id_len)).min(bytes.len())..];
+    let mut data = if rle {
+        read_rle(body, len, bytes_per_pixel)?
+    } else {
+        body.get(..len).ok_or_else(truncated)?.to_vec()
+    };
+
+    let row_len = width * bytes_per_pixel;
+    if descriptor & RIGHT_TO_LEFT != 0 {
+        for row in data.chunks_exact_mut(row_len.max(1)) {
+            row.reverse();
+            // Reversing the bytes reversed each pixel's channels too
+            row.chunks_exact_mut(bytes_per_pixel)
+                .for_each(|pixel| pixel.reverse());
+        }
+    }
+    if descriptor & TOP_TO_BOTTOM == 0 {
+        data = data
+            .rchunks_exact(row_len.max(1))
+            .flatten()
+            .copied()
+            .collect();
+    }
+    if bytes_per_pixel > 1 {
+        data.chunks_exact_mut(bytes_per_pixel)
+            .for_each(|pixel| pixel.swap(0, 2));
+    }
+
+    let (width, height) = (width as u32, height as u32);
+    let image = match bytes_per_pixel {
+        1 => GrayImage::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+        3 => RgbImage::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+        _ => RgbaImage::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+    };
+    Ok(image.expect("TGA data has the size of the image"))
+}
+
+fn truncated() -> FormatError {
+    FormatError("unexpected end of TGA data".into())
+}
+
+/// Expands RLE packets until there are `len` bytes. A packet that runs past
+/// the end of the image is an error, as it is for tinyrenderer.
+fn read_rle(mut body: &[u8], len: usize, bytes_per_pixel: usize) -> Result<Vec<u8>, FormatError> {
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        let (&packet, rest) = body.split_first().ok_or_else(truncated)?;
+        // Raw packets are followed by `count` pixels, repeats by one
+        let count = usize::from(packet & 0x7f) + 1;
+        let raw = packet < 0x80;
+        let taken = if raw {
+            count * bytes_per_pixel
+        } else {
+            bytes_per_pixel
+        };
+        let pixels = rest.get(..taken).ok_or_else(truncated)?;
+        body = &rest[taken..];
+        if data.len() + count * bytes_per_pixel > len {
+            return Err(FormatError("TGA has more pixels than its size".into()));
+        }
+        if raw {
+            data.extend_from_slice(pixels);
+        } else {
+            (0..count).for_each(|_| data.extend_from_slice(pixels));
+        }
+    }
+    Ok(data)
+}
+
+/// Like [`image::load_from_memory`], but decodes the file named `name` with
+/// [`decode`] if it ends in `.tga`. TGAs don't start with a magic number, so
+/// the name is the only way to tell.
+pub fn load_from_memory(bytes: &[u8], name: &str) -> ImageResult<DynamicImage> {
+    if is_tga(name) {
+        decode(bytes).map_err(|e| {
+            ImageError::Decoding(DecodingError::new(
+                ImageFormatHint::Exact(ImageFormat::Tga),
+                e,
+            ))
+        })
+    } else {
+        image::load_from_memory(bytes)
+    }
+}
+
+/// Like [`DynamicImage::save`], but writes `.tga` files with [`encode`] and
+/// tinyrenderer's default [`Options`].
+pub fn save(image: &DynamicImage, path: &Path) -> ImageResult<()> {
+    if !is_tga(&path.to_string_lossy()) {
+        return image.save(path);
+    }
+    let data = encode(image, Options::default()).map_err(|e| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Tga),
+            e,
+        ))
+    })?;
+    std::fs::write(path, data).map_err(ImageError::IoError)
+}
+
+fn is_tga(name: &str) -> bool {
+    name.rsplit_once('.')
+        .is_some_and(|(_, extension)| extension.eq_ignore_ascii_case("tga"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5x3 image with runs of repeated pixels between distinct ones, in
+    /// each of the layouts the encoder keeps.
+    fn images() -> [DynamicImage; 3] {
+        let rgba = RgbaImage::from_fn(5, 3, |x, y| {
+            let v = (x / 2 + y * 3) as u8 * 30;
+            image::Rgba([v, 255 - v, v / 2, 255 - v / 3])
+        });
+        [
+            DynamicImage::ImageRgba8(rgba.clone()).to_luma8().into(),
+            DynamicImage::ImageRgba8(rgba.clone()).to_rgb8().into(),
+            rgba.into(),
+        ]
+    }
+
+    #[test]
+    fn images_round_trip() {
+        for image in images() {
+            for rle in [false, true] {
+                for origin in [Origin::BottomLeft, Origin::TopLeft] {
+                    let options = Options { rle, origin };
+                    let bytes = encode(&image, options).unwrap();
+                    assert!(bytes.ends_with(FOOTER));
+                    assert_eq!(
+                        decode(&bytes).unwrap(),
+                        image,
+                        "{:?} with {:?}",
+                        image.color(),
+                        options
+                    );
+                }
+            }
+        }
+    }
+
+    /// Red, red, red, blue over green, green, white, black.
+    fn four_by_two() -> RgbImage {
+        let [r, g, b, w, k] = [
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 255],
+            [0, 0, 0],
+        ];
+        let pixels = [r, r, r, b, g, g, w, k];
+        RgbImage::from_raw(4, 2, pixels.concat()).unwrap()
+    }
+
+    /// The file tinyrenderer's `write_tga_file` writes for [`four_by_two`]:
+    /// the bottom row first, as a repeat of green, a raw white and black, a
+    /// repeat of red and a raw blue, all in BGR.
+    const FOUR_BY_TWO_TGA: &[u8] = &[
+        0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 2, 0, 24, 0, //
+        0x81, 0, 255, 0, //
+        0x01, 255, 255, 255, 0, 0, 0, //
+        0x82, 0, 0, 255, //
+        0x00, 255, 0, 0, //
+        0, 0, 0, 0, 0, 0, 0, 0, b'T', b'R', b'U', b'E', b'V', b'I', b'S', b'I', b'O', b'N', b'-',
+        b'X', b'F', b'I', b'L', b'E', b'.', 0,
+    ];
+
+    #[test]
+    fn rle_matches_tinyrenderer() {
+        let image = DynamicImage::ImageRgb8(four_by_two());
+        assert_eq!(encode(&image, Options::default()).unwrap(), FOUR_BY_TWO_TGA);
+    }
+
+    #[test]
+    fn both_origins_decode() {
+        let image = DynamicImage::ImageRgb8(four_by_two());
+        assert_eq!(decode(FOUR_BY_TWO_TGA).unwrap(), image);
+        // The same rows read top down are the image upside down
+        let mut top_left = FOUR_BY_TWO_TGA.to_vec();
+        top_left[17] = TOP_TO_BOTTOM;
+        assert_eq!(decode(&top_left).unwrap(), image.flipv());
+    }
+}
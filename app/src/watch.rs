@@ -0,0 +1,112 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::source::{AssetSource, FileSource, ReadFuture};
+
+use notify::{
+    event::{EventKind, ModifyKind},
+    RecommendedWatcher, RecursiveMode, Watcher,
+};
+
+/// Watches individual files and calls back with the path of any that change.
+///
+/// Editors often save by writing a new file and renaming it over the old one,
+/// which would silently end a watch on the file itself, so the watcher
+/// watches each file's directory and filters events down to the files it was
+/// asked about.
+pub struct FileWatcher {
+    files: Arc<Mutex<HashSet<PathBuf>>>,
+    dirs: HashSet<PathBuf>,
+    watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    pub fn new(on_change: impl Fn(PathBuf) + Send + 'static) -> notify::Result<Self> {
+        let files = Arc::new(Mutex::new(HashSet::<PathBuf>::new()));
+        let watched = files.clone();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    log::warn!("File watch error: {}", e);
+                    return;
+                }
+            };
+            if !matches!(
+                event.kind,
+                EventKind::Create(_)
+                    | EventKind::Modify(
+                        ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any
+                    )
+            ) {
+                return;
+            }
+            let watched = watched.lock().unwrap();
+            for path in event.paths {
+                if watched.contains(&path) {
+                    on_change(path);
+                }
+            }
+        })?;
+        Ok(Self {
+            files,
+            dirs: HashSet::new(),
+            watcher,
+        })
+    }
+
+    /// Starts watching `path`, which must exist. Returns the canonical form of
+    /// the path, which is what will be passed to the callback.
+    pub fn watch(&mut self, path: &Path) -> notify::Result<PathBuf> {
+        let path = path.canonicalize()?;
+        if let Some(dir) = path.parent() {
+            if self.dirs.insert(dir.to_path_buf()) {
+                self.watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            }
+        }
+        self.files.lock().unwrap().insert(path.clone());
+        Ok(path)
+    }
+}
+
+/// Reads assets from a directory like [`FileSource`], and watches every file
+/// that is read so that it can be loaded again when it changes.
+pub struct WatchedSource {
+    files: FileSource,
+    root: PathBuf,
+    watcher: Mutex<FileWatcher>,
+}
+
+impl WatchedSource {
+    /// Reads assets from `root`, calling `on_change` with the asset path of
+    /// any that change after being read.
+    pub fn new(root: &Path, on_change: impl Fn(String) + Send + 'static) -> notify::Result<Self> {
+        let root = root.canonicalize()?;
+        let prefix = root.clone();
+        let watcher = FileWatcher::new(move |path| {
+            if let Ok(path) = path.strip_prefix(&prefix) {
+                on_change(path.to_string_lossy().replace('\\', "/"));
+            }
+        })?;
+        Ok(Self {
+            files: FileSource::new(&root),
+            root,
+            watcher: Mutex::new(watcher),
+        })
+    }
+}
+
+impl AssetSource for WatchedSource {
+    fn read<'a>(&'a self, path: &'a str) -> ReadFuture<'a> {
+        Box::pin(async move {
+            let data = self.files.read(path).await?;
+            if let Err(e) = self.watcher.lock().unwrap().watch(&self.root.join(path)) {
+                log::warn!("Failed to watch {}: {}", path, e);
+            }
+            Ok(data)
+        })
+    }
+}